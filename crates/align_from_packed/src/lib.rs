@@ -3,6 +3,7 @@ use quote::quote;
 use syn::{ItemStruct, Path, parse_macro_input};
 
 /// Attribute: #[from_packed(PackedType)]
+/// Converts between the annotated runtime struct and its packed on-disk counterpart.
 #[proc_macro_attribute]
 pub fn from_packed(attr: TokenStream, item: TokenStream) -> TokenStream {
     let packed_ty = parse_macro_input!(attr as Path);
@@ -19,10 +20,31 @@ pub fn from_packed(attr: TokenStream, item: TokenStream) -> TokenStream {
         }
     });
 
+    // Generate: field: unpacked.field, used to write the runtime struct back out.
+    let packed_fields = input.fields.iter().map(|f| {
+        let name = f.ident.as_ref().expect("named fields only");
+        quote! { #name: unpacked.#name }
+    });
+
+    // Generate: ("field", offset_of!(PackedType, field), size_of::<FieldType>())
+    let layout = input.fields.iter().map(|f| {
+        let name = f.ident.as_ref().expect("named fields only");
+        let ty = &f.ty;
+        let label = name.to_string();
+        quote! {
+            (#label, core::mem::offset_of!(#packed_ty, #name), core::mem::size_of::<#ty>())
+        }
+    });
+
     // We implement From<&PackedType> to avoid moving a huge struct by value.
     let expanded = quote! {
         #input
 
+        impl #target_ident {
+            /// (name, byte offset, byte size) of every field as laid out in the packed type.
+            pub const PACKED_LAYOUT: &'static [(&'static str, usize, usize)] = &[#(#layout),*];
+        }
+
         impl From<& #packed_ty> for #target_ident {
             #[inline(always)]
             fn from(packed: & #packed_ty) -> Self {
@@ -30,6 +52,13 @@ pub fn from_packed(attr: TokenStream, item: TokenStream) -> TokenStream {
             }
         }
 
+        impl From<& #target_ident> for #packed_ty {
+            #[inline(always)]
+            fn from(unpacked: & #target_ident) -> Self {
+                Self { #(#packed_fields),* }
+            }
+        }
+
         // Optional convenience impl; calls the &T version to avoid code dup.
         impl From<#packed_ty> for #target_ident
         where
@@ -1,21 +1,21 @@
 use cine_py::file::CineFile;
-use criterion::{Criterion, black_box, criterion_group, criterion_main};
-use pyo3::prelude::*;
+use criterion::{Criterion, criterion_group, criterion_main};
 use rand::Rng;
+use std::hint::black_box;
 
 fn get_frame_benchmark(c: &mut Criterion) {
     // when this gets run from the makefile, the crates root gets set as the working dir.
-    let mut cine_file: CineFile = CineFile::new("./files/temp.cine");
+    let mut cine_file: CineFile = CineFile::new("./files/temp.cine").unwrap();
 
     c.bench_function("get_random_frame", |b| {
         b.iter_batched(
             // Setup closure: runs before each measurement.
             || {
-                let mut rng = rand::thread_rng();
-                rng.gen_range(0..400)
+                let mut rng = rand::rng();
+                rng.random_range(0..400)
             },
             // Measurement closure.
-            |frame_index| cine_file.get_frame(black_box(frame_index)),
+            |frame_index| cine_file.get_frame(black_box(frame_index), None),
             criterion::BatchSize::SmallInput,
         );
     });
@@ -23,21 +23,44 @@ fn get_frame_benchmark(c: &mut Criterion) {
 
 fn get_b64_benchmark(c: &mut Criterion) {
     // when this gets run from the makefile, the crates root gets set as the working dir.
-    let mut cine_file: CineFile = CineFile::new("./files/temp.cine");
+    let mut cine_file: CineFile = CineFile::new("./files/temp.cine").unwrap();
 
     c.bench_function("get_b64_frame", |b| {
         b.iter_batched(
             // Setup closure: runs before each measurement.
             || {
-                let mut rng = rand::thread_rng();
-                rng.gen_range(0..400)
+                let mut rng = rand::rng();
+                rng.random_range(0..400)
             },
             // Measurement closure.
-            |frame_index| cine_file.base64_png(black_box(frame_index)),
+            |frame_index| cine_file.base64_png(black_box(frame_index), false),
             criterion::BatchSize::SmallInput,
         );
     });
 }
 
-criterion_group!(benches, get_frame_benchmark, get_b64_benchmark);
+fn get_roi_benchmark(c: &mut Criterion) {
+    // when this gets run from the makefile, the crates root gets set as the working dir.
+    let mut cine_file: CineFile = CineFile::new("./files/temp.cine").unwrap();
+
+    c.bench_function("get_random_roi_128", |b| {
+        b.iter_batched(
+            // Setup closure: runs before each measurement.
+            || {
+                let mut rng = rand::rng();
+                rng.random_range(0..400)
+            },
+            // Measurement closure.
+            |frame_index| cine_file.get_roi(black_box(frame_index), 256, 256, 128, 128),
+            criterion::BatchSize::SmallInput,
+        );
+    });
+}
+
+criterion_group!(
+    benches,
+    get_frame_benchmark,
+    get_b64_benchmark,
+    get_roi_benchmark
+);
 criterion_main!(benches);
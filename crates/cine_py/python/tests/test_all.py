@@ -38,6 +38,14 @@ def test_setup():
     assert(cine_file.setup.ImWidth == cine_file.bitmap_info_header.bi_width)
     assert(cine_file.setup.ImHeight == cine_file.bitmap_info_header.bi_height)
 
+def test_setup_revision():
+    cine_file = cine_py.CineFile(fPth)
+
+    assert(cine_file.setup_length <= cine_file.setup.Length or cine_file.setup.Length == 0)
+    assert(cine_file.setup_revision >= cine_py.SetupRevision.Extended)
+    assert(cine_file.setup_field_present("dFrameRate"))
+    assert(cine_file.setup_value("RealBPP") == 10)
+
 def test_pix_length():
     cine_file = cine_py.CineFile(fPth)
 
@@ -56,12 +64,215 @@ def test_save_file():
 
     assert(os.path.exists(save_path))
 
+def test_export_sequences():
+    import os
+    cine_file = cine_py.CineFile(fPth)
+    tiffs = cine_file.export_tiff_sequence(0, 3, "./files/temp_{frame:06}.tiff")
+    dngs = cine_file.export_dng_sequence(0, 3, "./files/temp_{frame:06}.dng")
+
+    assert(len(tiffs) == 3 and len(dngs) == 3)
+    assert(all(os.path.exists(p) for p in tiffs + dngs))
+
+def test_export_video():
+    cine_file = cine_py.CineFile(fPth)
+    out = "./files/temp.avi"
+    cine_file.export_video(0, 10, out, fps=25)
+
+    with open(out, "rb") as f:
+        data = f.read()
+    assert(data[:4] == b"RIFF" and data[8:12] == b"AVI ")
+
+    # Walk the top level chunks to the index, JPEG data may contain any bytes.
+    pos, movi, index = 12, None, []
+    while pos < len(data):
+        fourcc, size = data[pos:pos + 4], int.from_bytes(data[pos + 4:pos + 8], "little")
+        if fourcc == b"LIST" and data[pos + 8:pos + 12] == b"movi":
+            movi = pos + 8
+        if fourcc == b"idx1":
+            index = [data[i:i + 16] for i in range(pos + 8, pos + 8 + size, 16)]
+        pos += 8 + size + size % 2
+    assert(len(index) == 10)
+    for entry in index:
+        offset = int.from_bytes(entry[8:12], "little")
+        assert(entry[:4] == b"00dc" and data[movi + offset:movi + offset + 4] == b"00dc")
+
+def test_preview():
+    cine_file = cine_py.CineFile(fPth)
+    gif = cine_file.preview(0, 20, stride=4, max_size=128)
+    apng = cine_file.preview(0, 20, stride=4, max_size=128, format="apng")
+
+    assert(gif[:6] == b"GIF89a")
+    assert(apng[:8] == b"\x89PNG\r\n\x1a\n" and b"acTL" in apng)
+
+def png_size(png):
+    # Width and height from the IHDR chunk.
+    return int.from_bytes(png[16:20], "big"), int.from_bytes(png[20:24], "big")
+
+def test_thumbnail_and_contact_sheet():
+    cine_file = cine_py.CineFile(fPth)
+    thumb = png_size(cine_file.thumbnail(0, 128))
+    assert(max(thumb) == 128)
+
+    sheet = png_size(cine_file.contact_sheet(6, 3, 128))
+    assert(sheet == (thumb[0] * 3, thumb[1] * 2))
+
 def test_base64():
     cine_file = cine_py.CineFile(fPth)
     frame_no=35
     b64 = cine_file.base64_png(frame_no)
     assert(type(b64) == str)
 
+def test_display_png():
+    cine_file = cine_py.CineFile(fPth)
+    png = cine_file.get_frame_as_png(0)
+    raw = cine_file.get_frame_as_png(0, raw=True)
+    # IHDR bit depth
+    assert(png[24] == 8 and raw[24] == 16)
+
+    frame = cine_file.get_display_frame(0)
+    width, height = cine_file.bitmap_info_header.bi_width, cine_file.bitmap_info_header.bi_height
+    assert(len(frame) == width * height)
+
+def test_geometry_override():
+    cine_file = cine_py.CineFile(fPth)
+    width, height = cine_file.frame_size()
+    frame = cine_file.get_frame(0)
+
+    cine_file.set_geometry(cine_py.Geometry(flip_v=True))
+    flipped = cine_file.get_frame(0)
+    assert(flipped[:width] == frame[-width:])
+
+    cine_file.set_geometry(cine_py.Geometry(rotate=90))
+    assert(cine_file.frame_size() == (height, width))
+    assert(len(cine_file.get_display_frame(0)) == width * height)
+
+    cine_file.set_geometry(None)
+    assert(cine_file.get_frame(0) == frame)
+
+    with pytest.raises(ValueError):
+        cine_file.set_geometry(cine_py.Geometry(crop=(width - 8, 0, 16, 16)))
+    geometry = cine_py.Geometry()
+    with pytest.raises(ValueError):
+        geometry.rotate = 45
+    assert(geometry.rotate == 0)
+
+def test_roi_and_framing():
+    cine_file = cine_py.CineFile(fPth)
+    width = cine_file.bitmap_info_header.bi_width
+    frame = cine_file.get_frame(0)
+    roi = cine_file.get_frame(0, roi=(16, 8, 32, 4))
+    assert(roi[:32] == frame[8 * width + 16:8 * width + 48])
+
+    cine_file.set_geometry(cine_py.Geometry(crop=(0, 0, 64, 32), resample=(32, 16), filter=cine_py.ResizeFilter.Lanczos3))
+    assert(cine_file.frame_size() == (32, 16))
+    assert(len(cine_file.get_frame(0)) == 32 * 16)
+
+    framing = cine_file.setup_framing()
+    cine_file.set_geometry(framing)
+    assert(len(cine_file.get_frame(0)) == cine_file.frame_size()[0] * cine_file.frame_size()[1])
+
+def test_get_roi():
+    cine_file = cine_py.CineFile(fPth)
+    assert(cine_file.get_roi(3, 100, 50, 128, 128) == cine_file.get_frame(3, roi=(100, 50, 128, 128)))
+    with pytest.raises(ValueError):
+        cine_file.get_roi(3, 0, 0, 0, 10)
+
+def test_frame_cache():
+    cine_file = cine_py.CineFile(fPth)
+    uncached = cine_file.get_frame(4)
+    cine_file.set_cache(64 << 20, read_ahead=2)
+    assert(cine_file.get_frame(4) == uncached)
+    assert(cine_file.get_frame(4) == uncached)
+    assert(cine_file.cache_info()[0] >= 1)
+    cine_file.set_cache(0)
+    assert(cine_file.cache_info() == (0, 0))
+
+def test_get_frame_into():
+    cine_file = cine_py.CineFile(fPth)
+    height, width, channels = cine_file.frame_shape()
+    out = np.empty((height, width) if channels == 1 else (height, width, channels), np.uint16)
+    for frame_no in range(3):
+        cine_file.get_frame_into(frame_no, out)
+        assert(out.ravel().tolist() == cine_file.get_frame(frame_no))
+    with pytest.raises(ValueError):
+        cine_file.get_frame_into(0, out[:-1])
+
+def test_frame_stages():
+    cine_file = cine_py.CineFile(fPth)
+    raw = cine_file.get_raw_frame(2)
+    assert(len(raw) == cine_file.bitmap_info_header.bi_size_image)
+    sensor = cine_file.get_sensor_frame(2)
+    height, width, _ = cine_file.frame_shape()
+    assert(len(sensor) == width * height)
+    # uint16 is the float32 frame scaled to 16 bits.
+    frame = np.asarray(cine_file.get_frame(2, dtype="float32"), np.float32)
+    expected = np.round(np.clip(frame, 0.0, 1.0) * 65535.0).astype(np.uint16)
+    assert(np.array_equal(np.asarray(cine_file.get_frame(2), np.uint16), expected))
+
+def test_float_frames():
+    cine_file = cine_py.CineFile(fPth)
+    frame = np.asarray(cine_file.get_frame(2, dtype="float32"), np.float32)
+    assert(frame.size == 768 * 416)
+    assert(frame.min() >= -0.1 and frame.max() <= 1.1)
+
+    height, width, _ = cine_file.frame_shape()
+    out = np.empty((height, width), np.float32)
+    cine_file.get_frame_into(2, out)
+    assert(np.array_equal(out.ravel(), frame))
+    with pytest.raises(ValueError):
+        cine_file.get_frame(2, dtype="int8")
+
+def test_dark_frame():
+    cine_file = cine_py.CineFile(fPth)
+    dark = cine_file.build_dark_frame(0, 5, method=cine_py.StackMethod.Median)
+    assert((dark.width, dark.height) == (768, 416))
+    dark.save("./files/temp_dark.tif")
+    loaded = cine_py.CalibrationFrame.load("./files/temp_dark.tif")
+    assert(loaded.pixels == dark.pixels)
+
+    pipeline = cine_py.Pipeline()
+    pipeline.dark_frame = loaded
+    cine_file.set_pipeline(pipeline)
+    # The dark of frames 0..5 takes most of their own content away.
+    frame = np.asarray(cine_file.get_frame(2, dtype="float32"))
+    assert(abs(np.median(frame)) < 0.05)
+
+def test_frame_stats():
+    cine_file = cine_py.CineFile(fPth)
+    stats = cine_file.frame_stats(0, 10)
+    assert((stats.height, stats.width, stats.channels) == cine_file.frame_shape())
+    assert(stats.count == 10)
+    frames = np.stack([np.asarray(cine_file.get_frame(f, dtype="float32")) for f in range(10)])
+    assert(np.allclose(np.asarray(stats.mean), frames.mean(axis=0), atol=1e-5))
+    assert(np.allclose(np.asarray(stats.std), frames.std(axis=0), atol=1e-4))
+    assert(np.array_equal(np.asarray(stats.max), frames.max(axis=0)))
+    assert(np.array_equal(np.asarray(stats.min), frames.min(axis=0)))
+
+def test_frame_summary():
+    cine_file = cine_py.CineFile(fPth)
+    summary = cine_file.frame_summary(bins=64)
+    assert(isinstance(summary, cine_py.FrameSummary))
+    count = cine_file.cine_file_header.image_count
+    assert(summary.frames == list(range(count)))
+    assert(summary.times == [cine_file.time_from_trigger(f) for f in range(count)])
+    histograms = np.asarray(summary.histograms).reshape(count, summary.channels, 64)
+    assert((histograms.sum(axis=(1, 2)) == 768 * 416).all())
+    assert(len(summary.mean) == len(summary.max) == len(summary.saturated) == count)
+
+def test_defect_map():
+    cine_file = cine_py.CineFile(fPth)
+    defects = cine_file.detect_defects(0, 20)
+    assert((defects.width, defects.height) == (768, 416))
+    defects.save("./files/temp_defects.txt")
+    loaded = cine_py.DefectMap.load("./files/temp_defects.txt")
+    assert(loaded.defects == defects.defects)
+
+    # A made up hot pixel gets the value of its neighbours.
+    cine_file.set_defect_map(cine_py.DefectMap(768, 416, [(10, 10, cine_py.DefectKind.Hot)]))
+    frame = np.asarray(cine_file.get_frame(0)).reshape(416, 768)
+    assert(abs(int(frame[10, 10]) - int(np.mean(frame[9:12, 9:12]))) < 64 * 32)
+    assert(cine_file.defect_map().defects[0][:2] == (10, 10))
+
 # def test_img_no_bytes():
 #     import numpy as np
 #     import cv2 
@@ -55,6 +55,38 @@ def test_save_file():
 
     assert(os.path.exists(save_path))
 
+def test_save_exr():
+    import os
+    cine_file = cine_py.CineFile(fPth)
+    exr_path = f"./files/{temp}.exr"
+    cine_file.save_exr(0, exr_path, half=True, white_balance=True)
+
+    assert(os.path.exists(exr_path))
+
+def test_color_spaces():
+    cine_file = cine_py.CineFile(fPth)
+    width, height = cine_file.bitmap_info_header.bi_width, cine_file.bitmap_info_header.bi_height
+    camera = cine_file.get_linear_frame(0, cine_py.ColorSpace.Camera, white_balance=False)
+    srgb = cine_file.get_linear_frame(0)
+    xyz = cine_file.get_linear_frame(0, cine_py.ColorSpace.Xyz)
+
+    assert(len(camera) == len(srgb) == len(xyz) == width * height * 3)
+    assert(camera != srgb)
+
+def test_display_overrides():
+    cine_file = cine_py.CineFile(fPth)
+    as_shot = cine_file.get_display_frame(0)
+
+    settings = cine_file.display_settings()
+    settings.saturation = 0.0
+    cine_file.set_display_settings(settings)
+    grey = cine_file.get_display_frame(0)
+    assert(grey != as_shot)
+    assert(all(abs(grey[i] - grey[i + 1]) <= 1 for i in range(0, 300, 3)))
+
+    cine_file.set_display_settings(None)
+    assert(cine_file.get_display_frame(0) == as_shot)
+
 # def test_img_no_bytes():
 #     import numpy as np
 #     import cv2 
@@ -66,4 +98,43 @@ def test_save_file():
 #     frame_no=10
 #     frame_bytes = cine_file.get_frame(frame_no)
 #     frame_bytes_as_np = np.frombuffer(frame_bytes, np.uint8)
-#     image_opencv = cv2.imdecode(frame_bytes_as_np, cv2.IMREAD_GRAYSCALE)
\ No newline at end of file
+#     image_opencv = cv2.imdecode(frame_bytes_as_np, cv2.IMREAD_GRAYSCALE)
+
+def test_pipeline():
+    cine_file = cine_py.CineFile(fPth)
+    pipeline = cine_file.pipeline()
+    assert(pipeline.white_balance and pipeline.demosaic)
+    assert(pipeline.color_space == cine_py.ColorSpace.LinearSrgb)
+    # Linear unless the setup's rendering is asked for.
+    assert(pipeline.gamma is None and pipeline.tone == [])
+    assert(cine_file.rendered_pipeline().white_balance)
+
+    pipeline.demosaic = False
+    pipeline.gamma = None
+    pipeline.tone = []
+    cine_file.set_pipeline(pipeline)
+    assert(cine_file.frame_shape() == (1080, 2048, 1))
+    assert(len(cine_file.get_frame(0)) == 2048 * 1080)
+
+    cine_file.set_pipeline(None)
+    assert(cine_file.frame_shape() == (1080, 2048, 3))
+
+def test_flat_field():
+    cine_file = cine_py.CineFile(fPth)
+    dark = cine_py.CalibrationFrame(2048, 1080, [64.0] * (2048 * 1080))
+    flat = cine_file.build_flat_field(0, 2, dark=dark)
+    flat.save("./files/temp_flat.tif")
+
+    pipeline = cine_py.Pipeline()
+    pipeline.demosaic = False
+    pipeline.flat_field = cine_py.CalibrationFrame.load("./files/temp_flat.tif")
+    cine_file.set_pipeline(pipeline)
+    assert(len(cine_file.get_frame(0, dtype="float32")) == 2048 * 1080)
+
+def test_frame_summary():
+    cine_file = cine_py.CineFile(fPth)
+    summary = cine_file.frame_summary(bins=16, start=0, end=2)
+    assert(summary.channels == 3)
+    histograms = np.asarray(summary.histograms).reshape(2, 3, 16)
+    # Half the mosaic is green.
+    assert((histograms[:, 1].sum(axis=1) == 2048 * 1080 // 2).all())
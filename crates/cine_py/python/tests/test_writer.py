@@ -0,0 +1,65 @@
+import pytest
+import cine_py
+import numpy as np
+
+save_path="./files/synthetic.cine"
+
+def test_write_synthetic():
+    width, height = 64, 32
+    setup = cine_py.Setup(width, height, 10, 1000)
+    frames = [np.full(width*height, i*100, dtype=np.uint16) for i in range(4)]
+
+    writer = cine_py.CineWriter(save_path, setup, len(frames))
+    for frame in frames:
+        writer.write_frame(frame.tolist())
+    writer.finish()
+
+    cine_file = cine_py.CineFile(save_path)
+    assert(cine_file.cine_file_header.image_count == len(frames))
+    assert(cine_file.bitmap_info_header.bi_width == width)
+    assert(cine_file.setup_revision == cine_py.SetupRevision.Current)
+
+    frame = np.asarray(cine_file.get_sensor_frame(2), dtype=np.uint16)
+    assert(np.all(frame == 200))
+
+def test_write_too_few_frames():
+    setup = cine_py.Setup(64, 32)
+    writer = cine_py.CineWriter(save_path, setup, 2)
+    writer.write_frame([0] * (64*32))
+    with pytest.raises(ValueError):
+        writer.finish()
+
+def test_trim():
+    trim_path = "./files/synthetic_trim.cine"
+    setup = cine_py.Setup(64, 32)
+    writer = cine_py.CineWriter(save_path, setup, 6)
+    for i in range(6):
+        writer.write_frame([i] * (64*32))
+    writer.finish()
+
+    cine_file = cine_py.CineFile(save_path)
+    cine_file.trim(2, 5, trim_path)
+
+    trimmed = cine_py.CineFile(trim_path)
+    assert(trimmed.cine_file_header.image_count == 3)
+    assert(trimmed.cine_file_header.first_image_no == 2)
+    assert(trimmed.get_sensor_frame(0)[0] == 2)
+
+def test_merge():
+    paths = [f"./files/synthetic_segment_{i}.cine" for i in range(2)]
+    merge_path = "./files/synthetic_merged.cine"
+    setup = cine_py.Setup(64, 32)
+    for i, path in enumerate(paths):
+        writer = cine_py.CineWriter(path, setup, 3, first_image_no=i*3)
+        for _ in range(3):
+            writer.write_frame([i] * (64*32))
+        writer.finish()
+
+    cine_py.merge(paths, merge_path)
+    with pytest.raises(OSError):
+        cine_py.merge(paths + ["./files/missing.cine"], merge_path)
+
+    merged = cine_py.CineFile(merge_path)
+    assert(merged.cine_file_header.image_count == 6)
+    assert(merged.get_sensor_frame(4)[0] == 1)
+    assert(merged.get_frame_time(5).as_secs_f64() > merged.get_frame_time(0).as_secs_f64())
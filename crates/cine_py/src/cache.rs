@@ -0,0 +1,274 @@
+// Decoded frame cache for scrubbing back and forth, with a background thread decoding ahead in
+// the playback direction.
+
+use crate::defects::DefectMap;
+use crate::file::CineFile;
+use crate::pipeline::Pipeline;
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Processed pixels and their channel count.
+pub type Frame = (Arc<[f32]>, usize);
+
+/// Least recently used cache of processed frames (pixels and channel count), bounded in bytes.
+/// Pixels are shared, so a hit hands the frame out without copying it.
+pub struct FrameCache {
+    max_bytes: usize,
+    bytes: usize,
+    // Each frame with the generation it was last used in.
+    frames: HashMap<usize, (Frame, u64)>,
+    // (generation, frame) in order of use, least recent first. Using a frame again pushes a new
+    // entry rather than moving the old one, which goes stale and is skipped.
+    order: VecDeque<(u64, usize)>,
+    generation: u64,
+}
+
+impl FrameCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            bytes: 0,
+            frames: HashMap::new(),
+            order: VecDeque::new(),
+            generation: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    pub fn contains(&self, frame_no: usize) -> bool {
+        self.frames.contains_key(&frame_no)
+    }
+
+    /// Looks a frame up, marking it as the most recently used.
+    pub fn get(&mut self, frame_no: usize) -> Option<&Frame> {
+        let generation = self.next_generation();
+        self.frames.get_mut(&frame_no)?.1 = generation;
+        self.order.push_back((generation, frame_no));
+        self.compact();
+        self.frames.get(&frame_no).map(|(frame, _)| frame)
+    }
+
+    /// Stores a frame, evicting the least recently used ones to stay within the byte budget.
+    /// Frames larger than the whole budget aren't kept.
+    pub fn insert(&mut self, frame_no: usize, pixels: Arc<[f32]>, channels: usize) {
+        let size = pixels.len() * size_of::<f32>();
+        if size > self.max_bytes {
+            return;
+        }
+        if let Some(((old, _), _)) = self.frames.remove(&frame_no) {
+            self.bytes -= old.len() * size_of::<f32>();
+        }
+        while self.bytes + size > self.max_bytes {
+            let Some((generation, oldest)) = self.order.pop_front() else {
+                break;
+            };
+            if self
+                .frames
+                .get(&oldest)
+                .is_some_and(|(_, used)| *used == generation)
+            {
+                let ((old, _), _) = self.frames.remove(&oldest).unwrap();
+                self.bytes -= old.len() * size_of::<f32>();
+            }
+        }
+        let generation = self.next_generation();
+        self.frames
+            .insert(frame_no, ((pixels, channels), generation));
+        self.order.push_back((generation, frame_no));
+        self.bytes += size;
+        self.compact();
+    }
+
+    /// Drops every frame, keeping the budget.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.order.clear();
+        self.bytes = 0;
+    }
+
+    fn next_generation(&mut self) -> u64 {
+        self.generation += 1;
+        self.generation
+    }
+
+    // Drops the stale entries once they outnumber the live ones, so `order` stays within twice
+    // the frame count and each use is O(1) amortised.
+    fn compact(&mut self) {
+        if self.order.len() > 2 * self.frames.len() + 16 {
+            let frames = &self.frames;
+            self.order.retain(|(generation, f)| {
+                frames.get(f).is_some_and(|(_, used)| used == generation)
+            });
+        }
+    }
+}
+
+// What the read-ahead thread is asked to do.
+enum Request {
+    // Frames to decode, in order.
+    Frames(Vec<usize>),
+    // New processing, numbered so frames decoded under the old one can be told apart.
+    Settings(u64, Pipeline, Option<DefectMap>),
+}
+
+/// A background thread with its own handle on the file, running requested frames through the
+/// handle's pipeline.
+pub struct ReadAhead {
+    /// Number of frames to decode ahead of the last one asked for.
+    pub count: usize,
+    // Number of the settings in use, see `Request::Settings`.
+    settings: u64,
+    requests: Option<Sender<Request>>,
+    // Behind a mutex only so the file handle stays `Sync` for Python.
+    results: Mutex<Receiver<(u64, usize, Frame)>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl ReadAhead {
+    /// Starts the thread on `cine_file`, a handle set up like the one being read ahead for.
+    pub fn spawn(mut cine_file: CineFile, count: usize) -> Self {
+        let (requests, request_rx) = mpsc::channel::<Request>();
+        let (result_tx, results) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            let mut pipeline = cine_file.pipeline();
+            let mut settings = 0;
+            let mut queue = VecDeque::new();
+            loop {
+                // Waits for work when there's none, otherwise only takes what's already sent.
+                let request = match queue.is_empty() {
+                    true => request_rx.recv().map_err(|_| TryRecvError::Disconnected),
+                    false => request_rx.try_recv(),
+                };
+                match request {
+                    // Newer requests replace whatever is left of older ones.
+                    Ok(Request::Frames(frames)) => queue = VecDeque::from(frames),
+                    Ok(Request::Settings(number, new_pipeline, defect_map)) => {
+                        (settings, pipeline) = (number, new_pipeline);
+                        cine_file.defect_map = defect_map;
+                        queue.clear();
+                    }
+                    Err(TryRecvError::Disconnected) => return,
+                    Err(TryRecvError::Empty) => {}
+                }
+                if let Some(frame_no) = queue.pop_front()
+                    && let Ok((pixels, channels)) = pipeline.process(&mut cine_file, frame_no)
+                    && result_tx
+                        .send((settings, frame_no, (pixels.into(), channels)))
+                        .is_err()
+                {
+                    return;
+                }
+            }
+        });
+        Self {
+            count,
+            settings: 0,
+            requests: Some(requests),
+            results: Mutex::new(results),
+            worker: Some(worker),
+        }
+    }
+
+    /// Replaces the outstanding request with `frames`, decoded in order.
+    pub fn request(&self, frames: Vec<usize>) {
+        self.send(Request::Frames(frames));
+    }
+
+    /// Switches the thread to `pipeline` and `defect_map`, dropping what it was asked for and
+    /// whatever it decoded under the old ones.
+    pub fn update(&mut self, pipeline: Pipeline, defect_map: Option<DefectMap>) {
+        self.settings += 1;
+        self.send(Request::Settings(self.settings, pipeline, defect_map));
+    }
+
+    /// Frames decoded since the last call, without waiting.
+    pub fn finished(&self) -> Vec<(usize, Frame)> {
+        match self.results.lock() {
+            Ok(results) => results
+                .try_iter()
+                .filter(|(settings, ..)| *settings == self.settings)
+                .map(|(_, frame_no, frame)| (frame_no, frame))
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn send(&self, request: Request) {
+        if let Some(requests) = &self.requests {
+            // A dead worker just means no more read-ahead.
+            let _ = requests.send(request);
+        }
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        // Closing the request channel stops the worker after its current frame.
+        self.requests.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        // Room for two 4 pixel frames.
+        let mut cache = FrameCache::new(32);
+        cache.insert(0, vec![0.0; 4].into(), 1);
+        cache.insert(1, vec![1.0; 4].into(), 1);
+        assert!(cache.get(0).is_some());
+        cache.insert(2, vec![2.0; 4].into(), 1);
+
+        // 1 was the least recently used.
+        assert!(!cache.contains(1));
+        assert!(cache.contains(0) && cache.contains(2));
+        assert_eq!(cache.bytes(), 32);
+
+        cache.insert(3, vec![3.0; 16].into(), 1);
+        assert!(!cache.contains(3));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_repeated_use() {
+        let mut cache = FrameCache::new(32);
+        cache.insert(0, vec![0.0; 4].into(), 1);
+        cache.insert(1, vec![1.0; 4].into(), 1);
+        // Stale entries of 0 don't pile up or count as uses of it.
+        for _ in 0..100 {
+            cache.get(0);
+            cache.get(1);
+        }
+        assert!(cache.order.len() <= 2 * cache.len() + 16);
+        cache.insert(2, vec![2.0; 4].into(), 1);
+        assert!(!cache.contains(0));
+        assert!(cache.contains(1) && cache.contains(2));
+
+        // Re-inserting a frame replaces it in place.
+        cache.insert(2, vec![3.0; 4].into(), 1);
+        assert_eq!(cache.bytes(), 32);
+        assert_eq!(*cache.get(2).unwrap().0, [3.0; 4]);
+    }
+}
@@ -0,0 +1,262 @@
+// Per pixel calibration frames, built by stacking reference recordings and kept as 32-bit float
+// TIFFs so other tools can open them too.
+
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::encoder::{TiffEncoder, colortype};
+
+/// How frames are combined per pixel.
+#[pyclass(module = "cinepy", eq, eq_int)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StackMethod {
+    /// Streams through the frames.
+    #[default]
+    Mean,
+    /// Robust against flickering pixels, but holds every frame in memory.
+    Median,
+}
+
+/// Per pixel values in stored frame layout, the mosaic for colour sensors. Dark frames hold
+/// linear sensor values (see `CineFile.build_dark_frame`), flat fields hold gains (see
+/// `CineFile.build_flat_field`).
+#[pyclass(module = "cinepy", get_all)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationFrame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<f32>,
+}
+
+#[pymethods]
+impl CalibrationFrame {
+    #[new]
+    pub fn new(width: u32, height: u32, pixels: Vec<f32>) -> PyResult<Self> {
+        if pixels.len() != width as usize * height as usize {
+            return Err(PyValueError::new_err(format!(
+                "{} values don't make a {width}x{height} frame",
+                pixels.len()
+            )));
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Writes the frame as a single channel 32-bit float TIFF.
+    pub fn save(&self, path: &str) -> PyResult<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        TiffEncoder::new(writer)
+            .and_then(|mut tiff| {
+                tiff.write_image::<colortype::Gray32Float>(self.width, self.height, &self.pixels)
+            })
+            .map_err(|e| PyIOError::new_err(e.to_string()))
+    }
+
+    /// Reads a frame written by `save`, or any single channel 32-bit float TIFF.
+    #[staticmethod]
+    pub fn load(path: &str) -> PyResult<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let tiff_error = |e: tiff::TiffError| PyIOError::new_err(e.to_string());
+        let mut decoder = Decoder::new(reader).map_err(tiff_error)?;
+        let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+        match decoder.read_image().map_err(tiff_error)? {
+            DecodingResult::F32(pixels) => Self::new(width, height, pixels),
+            _ => Err(PyValueError::new_err(
+                "Calibration frames are 32-bit float TIFFs",
+            )),
+        }
+    }
+
+    /// Mean of all pixels.
+    pub fn mean(&self) -> f32 {
+        (self.pixels.iter().map(|v| *v as f64).sum::<f64>() / self.pixels.len().max(1) as f64)
+            as f32
+    }
+}
+
+/// Combines equally sized frames per pixel, one frame at a time.
+pub struct Stack {
+    method: StackMethod,
+    count: usize,
+    sums: Vec<f64>,
+    frames: Vec<Vec<f32>>,
+}
+
+impl Stack {
+    pub fn new(method: StackMethod, pixel_count: usize) -> Self {
+        Self {
+            method,
+            count: 0,
+            sums: match method {
+                StackMethod::Mean => vec![0.0; pixel_count],
+                StackMethod::Median => Vec::new(),
+            },
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, frame: Vec<f32>) {
+        match self.method {
+            StackMethod::Mean => self
+                .sums
+                .iter_mut()
+                .zip(&frame)
+                .for_each(|(sum, v)| *sum += *v as f64),
+            StackMethod::Median => self.frames.push(frame),
+        }
+        self.count += 1;
+    }
+
+    /// The combined frame, empty when nothing was added.
+    pub fn finish(self) -> Vec<f32> {
+        if self.count == 0 {
+            return Vec::new();
+        }
+        match self.method {
+            StackMethod::Mean => self
+                .sums
+                .iter()
+                .map(|sum| (sum / self.count as f64) as f32)
+                .collect(),
+            StackMethod::Median => {
+                let mut column = vec![0.0f32; self.count];
+                (0..self.frames[0].len())
+                    .map(|i| {
+                        column
+                            .iter_mut()
+                            .zip(&self.frames)
+                            .for_each(|(v, frame)| *v = frame[i]);
+                        median(&mut column)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Flat field gains are kept within 1 / `MAX_FLAT_GAIN` to `MAX_FLAT_GAIN`, so a stuck or
+/// unlit pixel in the flat can't blow a pixel up or black it out.
+pub const MAX_FLAT_GAIN: f32 = 8.0;
+
+/// Gains evening out a stacked, black subtracted `flat` of uniform illumination. Each pixel is
+/// scaled to the mean of its colour in the 2x2 CFA `pattern` (of the whole frame for gray
+/// sensors), so the colour balance stays. Pixels below `min_signal` times that mean are taken as
+/// dead or unlit and keep a gain of 1, the rest are clamped to `MAX_FLAT_GAIN`.
+pub fn flat_field_gains(
+    flat: &[f32],
+    width: usize,
+    pattern: Option<[u8; 4]>,
+    min_signal: f32,
+) -> Vec<f32> {
+    let color_at = |i: usize| match pattern {
+        Some(pattern) => pattern[(i / width % 2) * 2 + i % width % 2] as usize,
+        None => 0,
+    };
+    let mut sums = [0.0f64; 3];
+    let mut counts = [0usize; 3];
+    for (i, v) in flat.iter().enumerate().filter(|(_, v)| **v > 0.0) {
+        sums[color_at(i)] += *v as f64;
+        counts[color_at(i)] += 1;
+    }
+    let means = [0, 1, 2].map(|c| (sums[c] / counts[c].max(1) as f64) as f32);
+    flat.iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let mean = means[color_at(i)];
+            match *v > 0.0 && *v >= min_signal * mean {
+                true => (mean / v).clamp(1.0 / MAX_FLAT_GAIN, MAX_FLAT_GAIN),
+                false => 1.0,
+            }
+        })
+        .collect()
+}
+
+// Median of a non-empty slice, reordering it. Even counts take the mean of the middle two.
+fn median(values: &mut [f32]) -> f32 {
+    let (len, mid) = (values.len(), values.len() / 2);
+    let (below, upper, _) = values.select_nth_unstable_by(mid, f32::total_cmp);
+    let upper = *upper;
+    match len % 2 {
+        1 => upper,
+        _ => {
+            let lower = below.iter().copied().fold(f32::MIN, f32::max);
+            (lower + upper) / 2.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFile;
+
+    #[test]
+    fn test_stack() {
+        let frames = [
+            vec![1.0, 4.0],
+            vec![2.0, 40.0],
+            vec![3.0, 5.0],
+            vec![6.0, 6.0],
+        ];
+        let mut mean = Stack::new(StackMethod::Mean, 2);
+        let mut median = Stack::new(StackMethod::Median, 2);
+        for frame in &frames {
+            mean.add(frame.clone());
+            median.add(frame.clone());
+        }
+        assert_eq!(mean.finish(), vec![3.0, 13.75]);
+        assert_eq!(median.finish(), vec![2.5, 5.5]);
+    }
+
+    #[test]
+    fn test_flat_field_gains() {
+        // Vignetted gray frame, the corners get the gain.
+        let gains = flat_field_gains(&[0.5, 1.0, 1.0, 0.5], 2, None, 0.1);
+        assert_eq!(gains, vec![1.5, 0.75, 0.75, 1.5]);
+
+        // gb/rg with a dim blue, each colour is evened out on its own.
+        let flat = [1.0, 0.25, 1.0, 1.0, 0.8, 1.0, 0.8, 1.0, 1.0, 0.0, 1.0, 0.25];
+        let gains = flat_field_gains(&flat, 4, Some([1, 2, 0, 1]), 0.1);
+        assert_eq!(
+            gains,
+            [1.0, 2.0, 1.0, 0.5, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 2.0]
+        );
+    }
+
+    #[test]
+    fn test_flat_field_near_black() {
+        // A dead pixel just above zero keeps a gain of 1 instead of 100s.
+        let flat = [1.0, 1.0, 1.0, 0.001, 1.0, 1.0];
+        let gains = flat_field_gains(&flat, 3, None, 0.1);
+        assert_eq!(gains[3], 1.0);
+        assert!(
+            gains
+                .iter()
+                .enumerate()
+                .all(|(i, g)| i == 3 || *g == gains[0])
+        );
+
+        // Dim and hot pixels above the threshold are clamped.
+        let mut flat = [1.0; 16];
+        (flat[0], flat[15]) = (0.05, 40.0);
+        let gains = flat_field_gains(&flat, 4, None, 0.001);
+        assert_eq!(gains[0], MAX_FLAT_GAIN);
+        assert_eq!(gains[15], 1.0 / MAX_FLAT_GAIN);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let file = TempFile::new("cine_py_dark.tif");
+        let path = file.path();
+        let frame = CalibrationFrame::new(3, 2, vec![0.5, 1.0, 1.5, 2.0, 2.5, 3.0]).unwrap();
+        frame.save(path).unwrap();
+        assert_eq!(CalibrationFrame::load(path).unwrap(), frame);
+        assert_eq!(frame.mean(), 1.75);
+        assert!(CalibrationFrame::new(3, 3, vec![0.0; 6]).is_err());
+    }
+}
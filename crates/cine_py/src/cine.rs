@@ -48,6 +48,38 @@ pub struct Time64 {
     pub seconds: u32,
 }
 
+// TIME64 counts seconds since 1970 plus fractions of 1/2^32 of a second.
+#[pymethods]
+impl Time64 {
+    #[new]
+    pub fn new(seconds: u32, fractions: u32) -> Self {
+        Self { fractions, seconds }
+    }
+
+    pub fn as_secs_f64(&self) -> f64 {
+        self.seconds as f64 + self.fractions as f64 / 4_294_967_296.0
+    }
+
+    #[staticmethod]
+    pub fn from_secs_f64(secs: f64) -> Self {
+        let seconds = secs.floor();
+        Self {
+            fractions: ((secs - seconds) * 4_294_967_296.0) as u32,
+            seconds: seconds as u32,
+        }
+    }
+}
+
+impl Time64 {
+    /// The 8 byte little endian form, fractions first.
+    pub fn from_le_bytes(bytes: &[u8]) -> Self {
+        Self {
+            fractions: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            seconds: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 #[pyclass(module = "cinepy", get_all)]
@@ -96,7 +128,7 @@ pub const MAXSTDSTRSZ_16: usize = 16;
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 #[from_packed(PackedSetup)]
-#[pyclass(module = "cinepy", get_all)]
+#[pyclass(module = "cinepy", get_all, set_all)]
 pub struct Setup {
     pub FrameRate16: u16,
     pub Shutter16: u16,
@@ -284,6 +316,250 @@ pub struct Setup {
     pub SensorOptions: u32,
 }
 
+impl Setup {
+    /// Recording rate in frames per second, preferring the precise `dFrameRate` when it's set.
+    pub fn frame_rate(&self) -> f64 {
+        if self.dFrameRate > 0.0 {
+            self.dFrameRate
+        } else {
+            self.FrameRate.max(1) as f64
+        }
+    }
+
+    /// Pixel value of black in the stored (unscaled) sensor data.
+    pub fn black_level(&self) -> u32 {
+        self.BlackLevel.max(0) as u32
+    }
+
+    /// Pixel value of saturation in the stored sensor data. Layouts without `WhiteLevel` leave it
+    /// zeroed, in which case the full range of `RealBPP` is assumed.
+    pub fn white_level(&self) -> u32 {
+        if self.WhiteLevel > 0 {
+            self.WhiteLevel as u32
+        } else {
+            (1 << self.RealBPP.clamp(8, 16)) - 1
+        }
+    }
+
+    pub fn camera_model(&self) -> String {
+        c_string(&self.CameraModel)
+    }
+}
+
+/// Converts a fixed size, nul terminated string field into a String.
+pub fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+// Setup is normally read from a file, the constructor gives a blank one for writing synthetic files.
+#[pymethods]
+impl Setup {
+    #[new]
+    #[pyo3(signature = (width, height, real_bpp=10, frame_rate=1000))]
+    pub fn new(width: u16, height: u16, real_bpp: u32, frame_rate: u32) -> Self {
+        let mut setup = Setup::from(PackedSetup::from_bytes(
+            &[0u8; std::mem::size_of::<PackedSetup>()],
+        ));
+        setup.Mark = u16::from_le_bytes(*b"ST");
+        setup.Length = std::mem::size_of::<PackedSetup>() as u16;
+        setup.ImWidth = width;
+        setup.ImHeight = height;
+        setup.RealBPP = real_bpp;
+        setup.RecBPP = real_bpp;
+        setup.WhiteLevel = (1 << real_bpp) - 1;
+        setup.FrameRate = frame_rate;
+        setup.dFrameRate = frame_rate as f64;
+        setup.fPbRate = 30.0;
+        setup.fGain = 1.0;
+        setup.fGamma = 1.0;
+        setup.fGainR = 1.0;
+        setup.fGainG = 1.0;
+        setup.fGainB = 1.0;
+        setup
+    }
+}
+
+//**  TAGGED INFORMATION BLOCKS   **//
+// Optional blocks stored between the setup and the image offsets, each one is
+// BlockSize (u32, including this 8 byte header), Type (u16), Reserved (u16) then the data.
+pub const TAG_ANALOG_DIGITAL_SIGNALS: u16 = 1000;
+pub const TAG_IMAGE_TIME_OLD: u16 = 1001;
+pub const TAG_TIME_ONLY: u16 = 1002; // TIME64 per image
+pub const TAG_EXPOSURE_ONLY: u16 = 1003; // u32 per image, 1/2^32 second units
+pub const TAG_RANGE_DATA: u16 = 1004;
+pub const TAG_BINARY_SIGNALS: u16 = 1005;
+pub const TAG_ANALOG_SIGNALS: u16 = 1006;
+pub const TAG_TIME_CODE: u16 = 1007;
+pub const TAGGED_BLOCK_HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct TaggedBlock {
+    pub block_type: u16,
+    pub reserved: u16,
+    pub data: Vec<u8>,
+}
+
+impl TaggedBlock {
+    pub fn block_size(&self) -> usize {
+        TAGGED_BLOCK_HEADER_SIZE + self.data.len()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.block_size());
+        bytes.extend_from_slice(&(self.block_size() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.block_type.to_le_bytes());
+        bytes.extend_from_slice(&self.reserved.to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    pub fn time_only(times: &[Time64]) -> Self {
+        let data = times
+            .iter()
+            .flat_map(|t| [t.fractions.to_le_bytes(), t.seconds.to_le_bytes()])
+            .flatten()
+            .collect();
+        Self {
+            block_type: TAG_TIME_ONLY,
+            reserved: 0,
+            data,
+        }
+    }
+
+    pub fn times(&self) -> Vec<Time64> {
+        self.data
+            .chunks_exact(8)
+            .map(Time64::from_le_bytes)
+            .collect()
+    }
+
+    /// Entry `index` of a time block, read without decoding the others.
+    pub fn time(&self, index: usize) -> Option<Time64> {
+        let entry = self.data.get(index * 8..(index + 1) * 8)?;
+        Some(Time64::from_le_bytes(entry))
+    }
+
+    pub fn exposures(&self) -> Vec<u32> {
+        self.data
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Entry `index` of an exposure block, read without decoding the others.
+    pub fn exposure(&self, index: usize) -> Option<u32> {
+        let entry = self.data.get(index * 4..(index + 1) * 4)?;
+        Some(u32::from_le_bytes(entry.try_into().unwrap()))
+    }
+
+    /// Whether the block holds one equally sized entry for each of `image_count` images.
+    pub fn is_per_image(&self, image_count: usize) -> bool {
+        let per_image_type = matches!(
+            self.block_type,
+            TAG_IMAGE_TIME_OLD
+                | TAG_TIME_ONLY
+                | TAG_EXPOSURE_ONLY
+                | TAG_RANGE_DATA
+                | TAG_BINARY_SIGNALS
+                | TAG_ANALOG_SIGNALS
+                | TAG_TIME_CODE
+        );
+        per_image_type && image_count > 0 && self.data.len().is_multiple_of(image_count)
+    }
+
+    /// The entries for images `start..end` of a block holding one equally sized entry per image.
+    /// Blocks that aren't per image (or don't divide evenly) are returned unchanged.
+    pub fn slice_images(&self, image_count: usize, start: usize, end: usize) -> Self {
+        if !self.is_per_image(image_count) {
+            return self.clone();
+        }
+        let entry_size = self.data.len() / image_count;
+        Self {
+            block_type: self.block_type,
+            reserved: self.reserved,
+            data: self.data[start * entry_size..end * entry_size].to_vec(),
+        }
+    }
+
+    pub fn exposure_only(exposures: &[u32]) -> Self {
+        Self {
+            block_type: TAG_EXPOSURE_ONLY,
+            reserved: 0,
+            data: exposures.iter().flat_map(|e| e.to_le_bytes()).collect(),
+        }
+    }
+}
+
+//**  SETUP REVISIONS   **//
+// The setup structure has grown with each Phantom software release and `Setup.Length` records how
+// much of it was actually written. Older files stop well before the newest fields, so anything past
+// `Length` is zero filled on read and these revisions describe which layout was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[pyclass(module = "cinepy", eq, eq_int, ord)]
+pub enum SetupRevision {
+    Legacy,          // ends before BlackLevel/WhiteLevel
+    ImageProcessing, // adds black/white levels, lens info and the fGain..cmUser processing values
+    Calibrated,      // adds crop/resample, fPbRate, cmCalib and the white balance temperature
+    Extended,        // adds RecBPP, camera model, dFrameRate and the sensor mode
+    Current,         // full layout up to SensorOptions
+}
+
+impl SetupRevision {
+    // The last field each revision is known to contain, newest first.
+    const MILESTONES: [(SetupRevision, &'static str); 4] = [
+        (SetupRevision::Current, "SensorOptions"),
+        (SetupRevision::Extended, "dFrameRate"),
+        (SetupRevision::Calibrated, "fWBCc"),
+        (SetupRevision::ImageProcessing, "WhiteLevel"),
+    ];
+
+    pub fn from_length(length: usize) -> Self {
+        Self::MILESTONES
+            .iter()
+            .find(|(_, field)| Setup::field_within(field, length).unwrap_or(false))
+            .map(|(revision, _)| *revision)
+            .unwrap_or(SetupRevision::Legacy)
+    }
+
+    /// The layout of files with `CineFileHeader.version`, for when `Setup.Length` is junk. Version
+    /// 0 files predate the image processing fields, later versions write the whole setup.
+    pub fn from_version(version: u16) -> Self {
+        match version {
+            0 => SetupRevision::Legacy,
+            _ => SetupRevision::Current,
+        }
+    }
+
+    /// Bytes of the setup this revision stores.
+    pub fn length(&self) -> usize {
+        let last = Self::MILESTONES
+            .iter()
+            .find(|(revision, _)| revision == self);
+        match last {
+            Some((_, field)) => {
+                Setup::packed_field(field).map_or(0, |(offset, size)| offset + size)
+            }
+            None => Setup::packed_field("BlackLevel").map_or(0, |(offset, _)| offset),
+        }
+    }
+}
+
+impl Setup {
+    /// Byte offset and size of a field in the packed layout, `None` if it isn't a setup field.
+    pub fn packed_field(name: &str) -> Option<(usize, usize)> {
+        Self::PACKED_LAYOUT
+            .iter()
+            .find(|(field, _, _)| *field == name)
+            .map(|(_, offset, size)| (*offset, *size))
+    }
+
+    /// Whether a field is stored entirely within the first `length` bytes of a setup block.
+    pub fn field_within(name: &str, length: usize) -> Option<bool> {
+        Self::packed_field(name).map(|(offset, size)| offset + size <= length)
+    }
+}
+
 // This only gets used to read the data from the file, because Phantom file formats are packed to reduce size.
 // This is good for size, bad for memory safety when accessing the fields. "Setup" is the runtime structure.
 #[repr(C, packed(1))]
@@ -474,3 +750,13 @@ pub struct PackedSetup {
     pub DaqOptions: u32, // these are bool32_t in the spec
     pub SensorOptions: u32,
 }
+
+impl PackedSetup {
+    /// The setup stored in the first `size_of::<PackedSetup>()` bytes of `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        assert!(bytes.len() >= std::mem::size_of::<Self>());
+        // SAFETY: the read is in bounds and unaligned, and every field is plain old data so any
+        // bytes make a valid (if meaningless) setup.
+        unsafe { bytes.as_ptr().cast::<Self>().read_unaligned() }
+    }
+}
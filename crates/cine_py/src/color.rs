@@ -0,0 +1,184 @@
+// Colour space maths shared by the exporters and the colour pipeline.
+// Matrices are row major 3x3, matching the layout of `Setup.cmCalib` and `Setup.cmUser`.
+
+use crate::cine;
+use pyo3::prelude::*;
+
+pub type Matrix3 = [f32; 9];
+
+pub const IDENTITY: Matrix3 = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+
+// Linear sRGB / Rec.709 primaries to CIE XYZ, D65 white.
+pub const SRGB_TO_XYZ: Matrix3 = [
+    0.4124, 0.3576, 0.1805, //
+    0.2126, 0.7152, 0.0722, //
+    0.0193, 0.1192, 0.9505,
+];
+
+pub fn mat3_mul(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut out = [0.0; 9];
+    for row in 0..3 {
+        for col in 0..3 {
+            out[row * 3 + col] = (0..3).map(|k| a[row * 3 + k] * b[k * 3 + col]).sum();
+        }
+    }
+    out
+}
+
+pub fn mat3_apply(m: &Matrix3, v: [f32; 3]) -> [f32; 3] {
+    [
+        m[0] * v[0] + m[1] * v[1] + m[2] * v[2],
+        m[3] * v[0] + m[4] * v[1] + m[5] * v[2],
+        m[6] * v[0] + m[7] * v[1] + m[8] * v[2],
+    ]
+}
+
+pub fn mat3_inverse(m: &Matrix3) -> Option<Matrix3> {
+    let det = m[0] * (m[4] * m[8] - m[5] * m[7]) - m[1] * (m[3] * m[8] - m[5] * m[6])
+        + m[2] * (m[3] * m[7] - m[4] * m[6]);
+    if det.abs() < 1e-9 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    Some([
+        (m[4] * m[8] - m[5] * m[7]) * inv_det,
+        (m[2] * m[7] - m[1] * m[8]) * inv_det,
+        (m[1] * m[5] - m[2] * m[4]) * inv_det,
+        (m[5] * m[6] - m[3] * m[8]) * inv_det,
+        (m[0] * m[8] - m[2] * m[6]) * inv_det,
+        (m[2] * m[3] - m[0] * m[5]) * inv_det,
+        (m[3] * m[7] - m[4] * m[6]) * inv_det,
+        (m[1] * m[6] - m[0] * m[7]) * inv_det,
+        (m[0] * m[4] - m[1] * m[3]) * inv_det,
+    ])
+}
+
+/// White balance gains for red, green and blue from the setup (`WBGain` of the first head).
+pub fn white_balance_gains(setup: &cine::Setup) -> [f32; 3] {
+    let gains = setup.WBGain[0];
+    if gains.R > 0.0 && gains.B > 0.0 {
+        [gains.R, 1.0, gains.B]
+    } else {
+        [1.0, 1.0, 1.0]
+    }
+}
+
+/// A matrix stored in the setup, `None` when the field is all zeroes (not set or not in this layout).
+pub fn setup_matrix(m: &[f32; 9]) -> Option<Matrix3> {
+    m.iter().any(|v| *v != 0.0).then_some(*m)
+}
+
+/// Per channel gains from `fGainR/G/B`, applied on top of the white balance.
+pub fn channel_gains(setup: &cine::Setup) -> [f32; 3] {
+    [setup.fGainR, setup.fGainG, setup.fGainB].map(|gain| if gain > 0.0 { gain } else { 1.0 })
+}
+
+/// Colour space of demosaiced output.
+#[pyclass(module = "cinepy", eq, eq_int)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// The sensor's own primaries, no matrix applied.
+    Camera,
+    /// Linear Rec.709 / sRGB primaries, D65 white.
+    LinearSrgb,
+    /// CIE XYZ.
+    Xyz,
+}
+
+/// White balance and colour matrix stage for demosaiced frames. `fWBTemp`/`fWBCc` only describe
+/// the illuminant the gains were set for, the gains and matrices carry everything needed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorCorrection {
+    pub gains: [f32; 3],
+    pub matrix: Matrix3,
+}
+
+impl ColorCorrection {
+    pub const NONE: Self = Self {
+        gains: [1.0; 3],
+        matrix: IDENTITY,
+    };
+
+    /// Gains from `WBGain` and `fGainR/G/B` (with `white_balance`), then `cmCalib` to get to
+    /// Rec.709 followed by `cmUser` when `EnableMatrices` is set.
+    pub fn from_setup(setup: &cine::Setup, space: ColorSpace, white_balance: bool) -> Self {
+        let gains = match white_balance {
+            true => {
+                let (wb, channel) = (white_balance_gains(setup), channel_gains(setup));
+                [wb[0] * channel[0], wb[1] * channel[1], wb[2] * channel[2]]
+            }
+            false => [1.0; 3],
+        };
+        let matrix = match space {
+            ColorSpace::Camera => IDENTITY,
+            ColorSpace::LinearSrgb => camera_to_rec709(setup),
+            ColorSpace::Xyz => mat3_mul(&SRGB_TO_XYZ, &camera_to_rec709(setup)),
+        };
+        Self { gains, matrix }
+    }
+
+    /// Applies the stage to interleaved RGB in place.
+    pub fn apply(&self, rgb: &mut [f32]) {
+        let skip_matrix = self.matrix == IDENTITY;
+        for pixel in rgb.chunks_exact_mut(3) {
+            let balanced = [
+                pixel[0] * self.gains[0],
+                pixel[1] * self.gains[1],
+                pixel[2] * self.gains[2],
+            ];
+            let out = match skip_matrix {
+                true => balanced,
+                false => mat3_apply(&self.matrix, balanced),
+            };
+            pixel.copy_from_slice(&out);
+        }
+    }
+}
+
+/// Camera RGB to linear Rec.709, `cmUser * cmCalib` when the user matrix is enabled. Missing
+/// matrices count as identity.
+pub fn camera_to_rec709(setup: &cine::Setup) -> Matrix3 {
+    let calib = setup_matrix(&setup.cmCalib).unwrap_or(IDENTITY);
+    match setup_matrix(&setup.cmUser) {
+        Some(user) if setup.EnableMatrices != 0 => mat3_mul(&user, &calib),
+        _ => calib,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inverse_round_trip() {
+        let inverse = mat3_inverse(&SRGB_TO_XYZ).unwrap();
+        let product = mat3_mul(&SRGB_TO_XYZ, &inverse);
+        for (value, expected) in product.iter().zip(IDENTITY) {
+            assert!((value - expected).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_color_correction() {
+        let mut setup = cine::Setup::new(4, 2, 12, 1000);
+        setup.WBGain[0].R = 2.0;
+        setup.WBGain[0].B = 1.5;
+        setup.fGainG = 0.5;
+        setup.cmCalib = [0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0];
+        setup.cmUser = [2.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 2.0];
+
+        let mut rgb = [1.0, 1.0, 1.0];
+        ColorCorrection::from_setup(&setup, ColorSpace::Camera, true).apply(&mut rgb);
+        assert_eq!(rgb, [2.0, 0.5, 1.5]);
+
+        // cmCalib swaps red and blue, cmUser is ignored until enabled.
+        let mut rgb = [1.0, 1.0, 1.0];
+        ColorCorrection::from_setup(&setup, ColorSpace::LinearSrgb, true).apply(&mut rgb);
+        assert_eq!(rgb, [1.5, 0.5, 2.0]);
+
+        setup.EnableMatrices = 1;
+        let mut rgb = [1.0, 1.0, 1.0];
+        ColorCorrection::from_setup(&setup, ColorSpace::LinearSrgb, false).apply(&mut rgb);
+        assert_eq!(rgb, [2.0, 2.0, 2.0]);
+    }
+}
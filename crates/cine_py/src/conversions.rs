@@ -3,11 +3,7 @@
 
 use std::fmt::Error;
 
-use crate::{file::CineFile, lut::LUT_10_TO_12};
-use pyo3::PyErr;
-use pyo3::conversion::IntoPyObject;
-use pyo3::prelude::*;
-use pyo3::types::PyList;
+use crate::decompress::Decompression;
 
 pub enum ColorFilterArray {
     Gray,        // 0 - gray sensor
@@ -25,41 +21,6 @@ pub enum ColorFilterArray {
     BottomRightGray, // 0x10000000
 }
 
-// pub type CFAType<'a> = Cow<'a, [u16]>;
-
-pub enum CFAType<'a> {
-    Gray(&'a [u16]),
-    Color(Vec<u16>),
-}
-
-impl<'a> CFAType<'a> {
-    pub fn unwrap(self) -> Vec<u16> {
-        match self {
-            CFAType::Gray(x) => x.to_owned(),
-            CFAType::Color(x) => x,
-        }
-    }
-}
-
-impl<'a, 'py> IntoPyObject<'py> for CFAType<'a> {
-    type Target = PyAny;
-    type Output = Bound<'py, PyAny>;
-    type Error = PyErr;
-
-    fn into_pyobject(self, py: Python<'py>) -> PyResult<Self::Output> {
-        match self {
-            CFAType::Gray(slice) => {
-                // Expose grayscale as a Python list of ints
-                Ok(PyList::new(py, slice).unwrap().into_any())
-            }
-            CFAType::Color(vec) => {
-                // Each pixel is [u16; 3], Python will see it as a list of tuples/lists
-                Ok(PyList::new(py, vec).unwrap().into_any())
-            }
-        }
-    }
-}
-
 impl ColorFilterArray {
     pub fn get_cfa(value: &u32) -> Result<Self, Error> {
         // Extract CFA type from least significat bytes (0x0000_00FF for u32)
@@ -88,168 +49,144 @@ impl ColorFilterArray {
         }
     }
 
-    pub fn apply_color_array<'a>(&self, pixels: &'a mut [u16]) -> Result<CFAType<'a>, Error> {
-        // The pixels need a lifetime of "a" because they are a referece from the decompression alog.
+    /// The 2x2 repeat pattern, top left first, as DNG CFA colours (0 = red, 1 = green, 2 = blue).
+    /// Gray and multi-head sensors have no single pattern.
+    pub fn pattern(&self) -> Option<[u8; 4]> {
         match self {
-            Self::Gray => Ok(Self::grayscale_10_to_16bit(pixels)?),
-            Self::Vri => Ok(Self::vri(pixels)?),
-            Self::VriV6 => Ok(Self::vri_v6(pixels)?),
-            Self::Bayer => Ok(Self::bayer(pixels)?),
-            Self::BayerFlip => Ok(Self::bayer_flip(pixels)?),
-            Self::BayerFlipPb => Ok(Self::bayer_flip_pb(pixels)?),
-            Self::BayerFlipPh => Ok(Self::bayer_flip_ph(pixels)?),
-            Self::TopLeftGray => Ok(Self::top_left_grey(pixels)?),
-            Self::TopRightGray => Ok(Self::top_right_grey(pixels)?),
-            Self::BottomLeftGray => Ok(Self::bottom_left_grey(pixels)?),
-            Self::BottomRightGray => Ok(Self::bottom_right_grey(pixels)?),
-            _ => Err(Error),
+            Self::Vri | Self::Bayer => Some([1, 2, 0, 1]), // gb/rg
+            Self::VriV6 | Self::BayerFlipPh => Some([2, 1, 1, 0]), // bg/gr
+            Self::BayerFlip => Some([0, 1, 1, 2]),         // rg/gb
+            Self::BayerFlipPb => Some([1, 0, 2, 1]),       // gr/bg
+            _ => None,
         }
     }
 
-    fn grayscale_10_to_16bit<'a>(pixels_10bit: &'a mut [u16]) -> Result<CFAType<'a>, Error> {
-        for pixel in pixels_10bit.iter_mut() {
-            // 10-bit packed set black level at 64 and white level at 1014
-            // if *pixel > 1014 {
-            //     *pixel = 1023
-            // } else if *pixel < 64 {
-            //     *pixel = 0
-            // }
-            // // Clamp the value to ensure it's a valid index for the LUT
-            // let clamped = (*pixel).min(1023);
-
-            // // Overwrite the original value with the new value from the LUT.
-            // // converts from 10-bits packed to 12-bits linear
-            // *pixel = LUT_10_TO_12[clamped as usize];
-            // Convert from 12-bits to 16-bits.
-            // Since the most significant 4 bits will always be empty in the 12-bit linear conversion,
-            // this remains a linear scaling transformation, ie. pix << n == pix * (2^n).
-            *pixel <<= 6;
+    /// Bilinear demosaic (see `demosaic_bilinear`) of a `width` pixel wide mosaic into
+    /// interleaved RGB in `out`, which must be three times its length, for the sensor's pattern.
+    /// The mosaic can be a window of the frame whose top left corner is at `origin`. Buffers are
+    /// the caller's, so they can be reused between frames. Gray and multi-head sensors are errors.
+    pub fn demosaic_into(
+        &self,
+        mosaic: &[f32],
+        width: usize,
+        (x, y): (usize, usize),
+        out: &mut [f32],
+    ) -> Result<(), Error> {
+        let pattern = self.pattern().ok_or(Error)?;
+        if width == 0 || !mosaic.len().is_multiple_of(width) || out.len() != mosaic.len() * 3 {
+            return Err(Error);
         }
-        Ok(CFAType::Gray(pixels_10bit))
+        let height = mosaic.len() / width;
+        demosaic_bilinear_into(mosaic, width, height, shift_pattern(pattern, x, y), out);
+        Ok(())
     }
+}
 
-    // gbrg/rggb sensor
-    fn vri<'a>(pixels_10bit: &'a [u16]) -> Result<CFAType<'a>, Error> {
-        unimplemented!()
-    }
-    // bggr/grbg sensor
-    fn vri_v6<'a>(pixels_10bit: &'a [u16]) -> Result<CFAType<'a>, Error> {
-        unimplemented!()
-    }
-    // gb/rg sensor
-    fn bayer<'a>(pixels_10bit: &'a [u16]) -> Result<CFAType<'a>, Error> {
-        let width = 2048;
-        let height = 1080;
-        let mut rgb_data: Vec<u16> = vec![0u16; (width * height * 3) as usize];
+/// `pattern` as seen from a window whose top left corner is at (`x`, `y`) in the mosaic.
+pub fn shift_pattern(pattern: [u8; 4], x: usize, y: usize) -> [u8; 4] {
+    std::array::from_fn(|i| pattern[((i / 2 + y) % 2) * 2 + (i % 2 + x) % 2])
+}
 
-        for y in 1..(height - 1) {
-            for x in 1..(width - 1) {
-                let idx: usize = (y * width + x) as usize;
-                let r: u16;
-                let g: u16;
-                let b: u16;
+/// Linearises stored sensor values and normalises them so black is 0.0 and white is 1.0.
+/// Nothing is clipped, values past white or below black are kept for analysis.
+pub fn linearise(
+    pixels: &[u16],
+    compression: &Decompression,
+    black_level: u32,
+    white_level: u32,
+) -> Vec<f32> {
+    let black = compression.to_linear(black_level as u16) as f32;
+    let white = compression.to_linear(white_level as u16) as f32;
+    let range = (white - black).max(1.0);
+    pixels
+        .iter()
+        .map(|p| (compression.to_linear(*p) as f32 - black) / range)
+        .collect()
+}
 
-                if y % 2 == 0 {
-                    if x % 2 == 0 {
-                        // G pixel on green-blue row
-                        r = (pixels_10bit[idx - 1] + pixels_10bit[idx + 1]) / 2;
-                        g = pixels_10bit[idx];
-                        b = (pixels_10bit[idx - width as usize]
-                            + pixels_10bit[idx + width as usize])
-                            / 2;
-                    } else {
-                        // B pixel
-                        r = (pixels_10bit[idx - width as usize - 1]
-                            + pixels_10bit[idx - width as usize + 1]
-                            + pixels_10bit[idx + width as usize - 1]
-                            + pixels_10bit[idx + width as usize + 1])
-                            / 4;
-                        g = (pixels_10bit[idx - 1]
-                            + pixels_10bit[idx + 1]
-                            + pixels_10bit[idx - width as usize]
-                            + pixels_10bit[idx + width as usize])
-                            / 4;
-                        b = pixels_10bit[idx];
-                    }
-                } else if x % 2 == 0 {
-                    // R pixel
-                    r = pixels_10bit[idx];
-                    g = (pixels_10bit[idx - 1]
-                        + pixels_10bit[idx + 1]
-                        + pixels_10bit[idx - width as usize]
-                        + pixels_10bit[idx + width as usize])
-                        / 4;
-                    b = (pixels_10bit[idx - width as usize - 1]
-                        + pixels_10bit[idx - width as usize + 1]
-                        + pixels_10bit[idx + width as usize - 1]
-                        + pixels_10bit[idx + width as usize + 1])
-                        / 4;
-                } else {
-                    // G pixel on red-green row
-                    r = (pixels_10bit[idx - width as usize] + pixels_10bit[idx + width as usize])
-                        / 2;
-                    g = pixels_10bit[idx];
-                    b = (pixels_10bit[idx - 1] + pixels_10bit[idx + 1]) / 2;
-                }
+/// Bilinear demosaic of a mosaic into interleaved RGB for any 2x2 `pattern` (see
+/// `ColorFilterArray::pattern`). Each missing colour is the mean of the neighbours in the 3x3
+/// window that carry it, edges just use the neighbours that exist.
+pub fn demosaic_bilinear(
+    mosaic: &[f32],
+    width: usize,
+    height: usize,
+    pattern: [u8; 4],
+) -> Vec<f32> {
+    let mut rgb = vec![0.0f32; width * height * 3];
+    demosaic_bilinear_into(mosaic, width, height, pattern, &mut rgb);
+    rgb
+}
 
-                let i: usize = idx * 3;
-                rgb_data[i] = r << 6;
-                rgb_data[i + 1] = g << 6;
-                rgb_data[i + 2] = b << 2;
+/// `demosaic_bilinear` into `rgb`, three times the length of the mosaic.
+pub fn demosaic_bilinear_into(
+    mosaic: &[f32],
+    width: usize,
+    height: usize,
+    pattern: [u8; 4],
+    rgb: &mut [f32],
+) {
+    let color_at = |x: usize, y: usize| pattern[(y % 2) * 2 + x % 2] as usize;
+    for y in 0..height {
+        for x in 0..width {
+            let mut sums = [0.0f32; 3];
+            let mut counts = [0u32; 3];
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let c = color_at(nx, ny);
+                    sums[c] += mosaic[ny * width + nx];
+                    counts[c] += 1;
+                }
+            }
+            let i = (y * width + x) * 3;
+            let own = color_at(x, y);
+            for c in 0..3 {
+                rgb[i + c] = if c == own {
+                    mosaic[y * width + x]
+                } else if counts[c] > 0 {
+                    sums[c] / counts[c] as f32
+                } else {
+                    0.0
+                };
             }
         }
-        Ok(CFAType::Color(rgb_data))
-    }
-
-    // rg/gb sensor
-    fn bayer_flip<'a>(pixels_10bit: &'a [u16]) -> Result<CFAType<'a>, Error> {
-        unimplemented!()
-    }
-    // gr/gb sensor
-    fn bayer_flip_pb<'a>(pixels_10bit: &'a [u16]) -> Result<CFAType<'a>, Error> {
-        unimplemented!()
-    }
-    // bg/gr sensor
-
-    fn bayer_flip_ph<'a>(pixels_10bit: &'a [u16]) -> Result<CFAType<'a>, Error> {
-        unimplemented!()
-    }
-
-    // TODO: This is for multi-head cameras. Since I don't have any to test,
-    // this will go unimplimented.
-    fn top_right_grey<'a>(pixels_10bit: &'a [u16]) -> Result<CFAType<'a>, Error> {
-        unimplemented!()
     }
+}
 
-    fn top_left_grey<'a>(pixels_10bit: &'a [u16]) -> Result<CFAType<'a>, Error> {
-        unimplemented!()
-    }
-    fn bottom_right_grey<'a>(pixels_10bit: &'a [u16]) -> Result<CFAType<'a>, Error> {
-        unimplemented!()
-    }
-    fn bottom_left_grey<'a>(pixels_10bit: &'a [u16]) -> Result<CFAType<'a>, Error> {
-        unimplemented!()
+/// Fast half size demosaic, each 2x2 cell of the mosaic becomes one RGB pixel (the two greens
+/// are averaged). Odd trailing rows and columns are dropped.
+pub fn demosaic_half(mosaic: &[f32], width: usize, height: usize, pattern: [u8; 4]) -> Vec<f32> {
+    let (half_width, half_height) = (width / 2, height / 2);
+    let mut rgb = vec![0.0f32; half_width * half_height * 3];
+    for y in 0..half_height {
+        for x in 0..half_width {
+            let mut sums = [0.0f32; 3];
+            let mut counts = [0u32; 3];
+            for (cell, c) in pattern.iter().enumerate() {
+                let (cx, cy) = (x * 2 + cell % 2, y * 2 + cell / 2);
+                sums[*c as usize] += mosaic[cy * width + cx];
+                counts[*c as usize] += 1;
+            }
+            let i = (y * half_width + x) * 3;
+            for c in 0..3 {
+                rgb[i + c] = sums[c] / counts[c].max(1) as f32;
+            }
+        }
     }
+    rgb
 }
 
-pub fn flip_vertical_16bit(data: &mut [u16], width: u32, height: u32) {
-    let row_len: usize = width as usize;
-    for y in 0..(height as usize / 2) {
+/// Flips an image upside down in place, `row_len` is the number of samples in a row.
+pub fn flip_vertical<T>(data: &mut [T], row_len: usize, height: usize) {
+    for y in 0..(height / 2) {
         let top_row: usize = y * row_len;
-        let bottom_row: usize = (height as usize - 1 - y) * row_len;
+        let bottom_row: usize = (height - 1 - y) * row_len;
         for x in 0..row_len {
             data.swap(top_row + x, bottom_row + x);
         }
     }
 }
 
-pub fn apply_gamma<'a>(cine_file: &CineFile, linear_pixels: &'a mut Vec<u16>) -> &'a Vec<u16> {
-    for pixel in linear_pixels.iter_mut() {
-        *pixel = (*pixel as f32).powf(1.0 / cine_file.setup.fGamma).round() as u16
-    }
-    linear_pixels
-}
-
 #[cfg(test)]
 mod tests {
 
@@ -260,4 +197,61 @@ mod tests {
         let b = 5;
         assert_eq!(a * b, 5);
     }
+
+    #[test]
+    fn test_demosaic_flat_field() {
+        // A flat grey scene stays flat whatever the pattern.
+        let mosaic = vec![0.5f32; 6 * 4];
+        let rgb = super::demosaic_bilinear(&mosaic, 6, 4, [1, 2, 0, 1]);
+        assert!(rgb.iter().all(|v| (*v - 0.5).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_demosaic_into_patterns() {
+        // R = 0.25, G = 0.5 and B = 0.75 come back at every pixel whatever the layout.
+        let (width, height) = (6, 4);
+        for cfa in 1..=6 {
+            let sensor = super::ColorFilterArray::get_cfa(&cfa).unwrap();
+            let pattern = sensor.pattern().unwrap();
+            let mosaic: Vec<f32> = (0..width * height)
+                .map(|i| [0.25, 0.5, 0.75][pattern[(i / width % 2) * 2 + i % 2] as usize])
+                .collect();
+            let mut rgb = vec![0.0; mosaic.len() * 3];
+            sensor
+                .demosaic_into(&mosaic, width, (0, 0), &mut rgb)
+                .unwrap();
+            assert!(rgb.chunks_exact(3).all(|p| p == [0.25, 0.5, 0.75]));
+
+            // A window starting on an odd row and column.
+            let window: Vec<f32> = (1..height)
+                .flat_map(|y| &mosaic[y * width + 1..(y + 1) * width])
+                .copied()
+                .collect();
+            let mut rgb = vec![0.0; window.len() * 3];
+            sensor.demosaic_into(&window, 5, (1, 1), &mut rgb).unwrap();
+            assert!(rgb.chunks_exact(3).all(|p| p == [0.25, 0.5, 0.75]));
+        }
+        let gray = super::ColorFilterArray::Gray;
+        assert!(
+            gray.demosaic_into(&[0.0; 4], 2, (0, 0), &mut [0.0; 12])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_demosaic_half() {
+        // G B / R G cells with R = 0.25, G = 0.5 and B = 0.75.
+        let mosaic = [0.5, 0.75, 0.5, 0.75, 0.25, 0.5, 0.25, 0.5];
+        let rgb = super::demosaic_half(&mosaic, 4, 2, [1, 2, 0, 1]);
+        assert_eq!(rgb, vec![0.25, 0.5, 0.75, 0.25, 0.5, 0.75]);
+    }
+
+    #[test]
+    fn test_linearise_levels() {
+        let packing = super::Decompression::Packed12Bit;
+        let linear = super::linearise(&[64, 1014, 2000], &packing, 64, 1014);
+        assert_eq!(linear[0], 0.0);
+        assert_eq!(linear[1], 1.0);
+        assert!(linear[2] > 1.0);
+    }
 }
@@ -1,3 +1,4 @@
+use crate::lut::LUT_10_TO_12;
 use std::fmt::Error;
 
 pub enum Decompression {
@@ -13,19 +14,84 @@ impl Decompression {
             _ => Err(Error),
         }
     }
+    pub fn from_bit_depth(bits: u32) -> Result<Self, Error> {
+        match bits {
+            10 => Ok(Self::Packed10Bit),
+            12 => Ok(Self::Packed12Bit),
+            _ => Err(Error),
+        }
+    }
+    /// The BITMAPINFOHEADER biCompression value for this packing.
+    pub fn bi_compression(&self) -> u32 {
+        match self {
+            Self::Packed10Bit => 256,
+            Self::Packed12Bit => 1024,
+        }
+    }
+    pub fn bit_depth(&self) -> u32 {
+        match self {
+            Self::Packed10Bit => 10,
+            Self::Packed12Bit => 12,
+        }
+    }
+    /// Number of bytes needed to store `pixel_count` packed pixels.
+    pub fn packed_size(&self, pixel_count: usize) -> usize {
+        (pixel_count * self.bit_depth() as usize).div_ceil(8)
+    }
+    /// Pixels and bytes in the smallest repeating unit of the packing; rows and spans that start
+    /// on a group can be unpacked on their own.
+    pub fn group_size(&self) -> (usize, usize) {
+        match self {
+            Self::Packed10Bit => (4, 5),
+            Self::Packed12Bit => (2, 3),
+        }
+    }
+    /// Converts a stored value to linear sensor units. 10-bit packed data is stored on a curve
+    /// and goes through the 10 to 12-bit LUT, 12-bit packed data is already linear.
+    pub fn to_linear(&self, value: u16) -> u16 {
+        match self {
+            Self::Packed10Bit => LUT_10_TO_12[value.min(1023) as usize],
+            Self::Packed12Bit => value,
+        }
+    }
+    /// The stored to linear mapping as a table indexed by stored value, `None` when stored values
+    /// are already linear.
+    pub fn linearisation_table(&self) -> Option<&'static [u16]> {
+        match self {
+            Self::Packed10Bit => Some(&LUT_10_TO_12),
+            Self::Packed12Bit => None,
+        }
+    }
     pub fn decompress(&self, data: &[u8]) -> Result<Vec<u16>, Error> {
+        let mut out = vec![0u16; self.unpacked_len(data.len())];
+        self.decompress_into(data, &mut out)?;
+        Ok(out)
+    }
+    /// Number of whole pixels `byte_count` packed bytes unpack to.
+    pub fn unpacked_len(&self, byte_count: usize) -> usize {
+        let (group_pixels, group_bytes) = self.group_size();
+        byte_count / group_bytes * group_pixels
+    }
+    /// `decompress` into a buffer the caller owns, so loops over frames can reuse one. Unpacks
+    /// the first `out.len()` pixels, which has to be whole groups (see `group_size`) that `data`
+    /// holds; trailing padding in `data` is ignored.
+    pub fn decompress_into(&self, data: &[u8], out: &mut [u16]) -> Result<(), Error> {
+        let (group_pixels, group_bytes) = self.group_size();
+        if !out.len().is_multiple_of(group_pixels)
+            || data.len() < out.len() / group_pixels * group_bytes
+        {
+            return Err(Error);
+        }
         match self {
-            Self::Packed10Bit => Ok(Self::decompress_10bit_packed(data)),
-            Self::Packed12Bit => Ok(Self::decompress_12bit_packed(data)),
+            Self::Packed10Bit => Self::decompress_10bit_packed(data, out),
+            Self::Packed12Bit => Self::decompress_12bit_packed(data, out),
         }
+        Ok(())
     }
-    /// Unpack 10-bit packed Bayer/greyscale into Vec<u16>
+    /// Unpack 10-bit packed Bayer/greyscale into `out`
     /// bi_compression=256 means that there is 4 pixles of 10-bit data stored in 5 bytes(40-bits).
-    fn decompress_10bit_packed(data: &[u8]) -> Vec<u16> {
-        let mut out: Vec<u16> = Vec::with_capacity(data.len() * 4 / 5);
-
-        let mut i: usize = 0;
-        while i + 4 < data.len() {
+    fn decompress_10bit_packed(data: &[u8], out: &mut [u16]) {
+        for (d, p) in data.chunks_exact(5).zip(out.chunks_exact_mut(4)) {
             // set the values for each 4 pixels. assume they're ordered as;
             // 00000000 00|000000 0000|0000 000000|00 00000000
             // ----p0-- --|----p1 ----|---- p2----|-- p3------
@@ -34,21 +100,17 @@ impl Decompression {
             // --------p0------- --------p1------- --------p2------- --------p3-------
             // and;
             // p0 starts in the top left corner of the frame.
-            out.push(((data[i] as u16) << 2) | ((data[i + 1] as u16) >> 6));
-            out.push((((data[i + 1] & 0b0011_1111) as u16) << 4) | ((data[i + 2] as u16) >> 4));
-            out.push((((data[i + 2] & 0b0000_1111) as u16) << 6) | ((data[i + 3] as u16) >> 2));
-            out.push((((data[i + 3] & 0b0000_0011) as u16) << 8) | (data[i + 4] as u16));
-            i += 5;
+            p[0] = ((d[0] as u16) << 2) | ((d[1] as u16) >> 6);
+            p[1] = (((d[1] & 0b0011_1111) as u16) << 4) | ((d[2] as u16) >> 4);
+            p[2] = (((d[2] & 0b0000_1111) as u16) << 6) | ((d[3] as u16) >> 2);
+            p[3] = (((d[3] & 0b0000_0011) as u16) << 8) | (d[4] as u16);
         }
-        out
     }
 
-    /// Unpack 12-bit packed Bayer/greyscale into Vec<u16>
+    /// Unpack 12-bit packed Bayer/greyscale into `out`
     /// bi_compression=1024 means that there is 2 pixles of 12-bit data stored in 3 bytes(24-bits).
-    fn decompress_12bit_packed(data: &[u8]) -> Vec<u16> {
-        let mut out = Vec::with_capacity(data.len() * 2 / 3);
-        let mut i: usize = 0;
-        while i + 3 < data.len() {
+    fn decompress_12bit_packed(data: &[u8], out: &mut [u16]) {
+        for (d, p) in data.chunks_exact(3).zip(out.chunks_exact_mut(2)) {
             // set the values for each 2 pixels. assume they're ordered as;
             // 00000000 0000|0000 00000000
             // ------p0 ----|---- p1-----|
@@ -57,10 +119,80 @@ impl Decompression {
             // --------p0------- --------p1-------
             // and;
             // p0 starts in the top left corner of the frame.
-            out.push(((data[i] as u16) << 4) | ((data[i + 1] as u16) >> 4));
-            out.push((((data[i + 1] & 0b0000_1111) as u16) << 8) | (data[i + 2] as u16));
-            i += 3
+            p[0] = ((d[0] as u16) << 4) | ((d[1] as u16) >> 4);
+            p[1] = (((d[1] & 0b0000_1111) as u16) << 8) | (d[2] as u16);
+        }
+    }
+
+    /// Pack pixels into the on-disk layout, the inverse of `decompress`.
+    /// Values are masked to the bit depth, so pass sensor values, not the scaled 16-bit output.
+    pub fn compress(&self, pixels: &[u16]) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::Packed10Bit if pixels.len().is_multiple_of(4) => {
+                Ok(Self::compress_10bit_packed(pixels))
+            }
+            Self::Packed12Bit if pixels.len().is_multiple_of(2) => {
+                Ok(Self::compress_12bit_packed(pixels))
+            }
+            _ => Err(Error),
+        }
+    }
+
+    /// Pack 4 pixels of 10-bit data into every 5 bytes, see `decompress_10bit_packed` for the layout.
+    fn compress_10bit_packed(pixels: &[u16]) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::with_capacity(pixels.len() * 5 / 4);
+        for p in pixels.chunks_exact(4) {
+            let (p0, p1, p2, p3) = (p[0] & 0x3FF, p[1] & 0x3FF, p[2] & 0x3FF, p[3] & 0x3FF);
+            out.push((p0 >> 2) as u8);
+            out.push((((p0 & 0b11) << 6) | (p1 >> 4)) as u8);
+            out.push((((p1 & 0b1111) << 4) | (p2 >> 6)) as u8);
+            out.push((((p2 & 0b11_1111) << 2) | (p3 >> 8)) as u8);
+            out.push((p3 & 0xFF) as u8);
+        }
+        out
+    }
+
+    /// Pack 2 pixels of 12-bit data into every 3 bytes, see `decompress_12bit_packed` for the layout.
+    fn compress_12bit_packed(pixels: &[u16]) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::with_capacity(pixels.len() * 3 / 2);
+        for p in pixels.chunks_exact(2) {
+            let (p0, p1) = (p[0] & 0xFFF, p[1] & 0xFFF);
+            out.push((p0 >> 4) as u8);
+            out.push((((p0 & 0b1111) << 4) | (p1 >> 8)) as u8);
+            out.push((p1 & 0xFF) as u8);
         }
         out
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_12bit_last_pair() {
+        // The final 3 bytes hold a whole pixel pair and must not be dropped.
+        let packed = [0x12, 0x34, 0x56, 0xAB, 0xCD, 0xEF];
+        let unpacked = Decompression::Packed12Bit.decompress(&packed).unwrap();
+        assert_eq!(unpacked, vec![0x123, 0x456, 0xABC, 0xDEF]);
+    }
+
+    #[test]
+    fn test_pack_round_trip() {
+        let pixels: Vec<u16> = (0..64u16).map(|i| i * 61).collect();
+        for bits in [10, 12] {
+            let packing = Decompression::from_bit_depth(bits).unwrap();
+            let mask = (1u16 << bits) - 1;
+            let packed = packing.compress(&pixels).unwrap();
+            assert_eq!(packed.len(), packing.packed_size(pixels.len()));
+            let unpacked = packing.decompress(&packed).unwrap();
+            let expected: Vec<u16> = pixels.iter().map(|p| p & mask).collect();
+            assert_eq!(unpacked, expected);
+
+            let mut reused = vec![0u16; pixels.len()];
+            packing.decompress_into(&packed, &mut reused).unwrap();
+            assert_eq!(reused, expected);
+            assert!(packing.decompress_into(&packed, &mut reused[1..]).is_err());
+        }
+    }
+}
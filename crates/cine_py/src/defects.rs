@@ -0,0 +1,254 @@
+// Hot, stuck and dead pixels; found from how pixels behave over a recording compared to their
+// neighbours, and filled in from the neighbours of the same colour when frames are decoded.
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::collections::HashSet;
+use std::fs;
+
+const HEADER: &str = "# cine_py defect map";
+
+/// Why a pixel is in a defect map.
+#[pyclass(module = "cinepy", eq, eq_int)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DefectKind {
+    /// Well above its neighbours.
+    Hot,
+    /// Doesn't change while its neighbours do.
+    Stuck,
+    /// Well below its neighbours where they get light.
+    Dead,
+}
+
+impl DefectKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DefectKind::Hot => "hot",
+            DefectKind::Stuck => "stuck",
+            DefectKind::Dead => "dead",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "hot" => Some(DefectKind::Hot),
+            "stuck" => Some(DefectKind::Stuck),
+            "dead" => Some(DefectKind::Dead),
+            _ => None,
+        }
+    }
+}
+
+/// Defective pixels of a sensor as (x, y, kind) in stored frame coordinates. Saved as text; a
+/// header line with the frame size followed by one `x,y,kind` line per pixel.
+#[pyclass(module = "cinepy", get_all)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefectMap {
+    pub width: u32,
+    pub height: u32,
+    pub defects: Vec<(u32, u32, DefectKind)>,
+}
+
+#[pymethods]
+impl DefectMap {
+    #[new]
+    pub fn new(width: u32, height: u32, defects: Vec<(u32, u32, DefectKind)>) -> PyResult<Self> {
+        if let Some((x, y, _)) = defects.iter().find(|(x, y, _)| *x >= width || *y >= height) {
+            return Err(PyValueError::new_err(format!(
+                "Defect at ({x}, {y}) is outside the {width}x{height} frame"
+            )));
+        }
+        Ok(Self {
+            width,
+            height,
+            defects,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> PyResult<()> {
+        let mut text = format!("{HEADER} {}x{}\n", self.width, self.height);
+        for (x, y, kind) in &self.defects {
+            text.push_str(&format!("{x},{y},{}\n", kind.name()));
+        }
+        Ok(fs::write(path, text)?)
+    }
+
+    #[staticmethod]
+    pub fn load(path: &str) -> PyResult<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines();
+        let size = lines
+            .next()
+            .and_then(|line| line.strip_prefix(HEADER))
+            .and_then(|size| size.trim().split_once('x'))
+            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+        let Some((width, height)) = size else {
+            return Err(PyValueError::new_err("Not a defect map"));
+        };
+        let mut defects = Vec::new();
+        for (n, line) in lines.enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let mut fields = line.split(',').map(str::trim);
+            let defect = match (fields.next(), fields.next(), fields.next()) {
+                (Some(x), Some(y), Some(kind)) => x
+                    .parse()
+                    .ok()
+                    .zip(y.parse().ok())
+                    .zip(DefectKind::from_name(kind)),
+                _ => None,
+            };
+            let Some(((x, y), kind)) = defect else {
+                return Err(PyValueError::new_err(format!(
+                    "Line {} of the defect map isn't x,y,kind",
+                    n + 2
+                )));
+            };
+            defects.push((x, y, kind));
+        }
+        Self::new(width, height, defects)
+    }
+}
+
+impl DefectMap {
+    /// Replaces each defective pixel of a `width` wide mosaic with the mean of its working
+    /// neighbours of the same colour; two pixels away for a 2x2 CFA, adjacent for gray sensors.
+    pub fn correct(&self, mosaic: &mut [u16], cfa: bool) {
+        let (width, height) = (self.width as i64, self.height as i64);
+        let step = if cfa { 2 } else { 1 };
+        let defective: HashSet<(i64, i64)> = self
+            .defects
+            .iter()
+            .map(|(x, y, _)| (*x as i64, *y as i64))
+            .collect();
+        for &(x, y) in &defective {
+            let (sum, count) = neighbours(x, y, step)
+                .filter(|&(nx, ny)| nx >= 0 && ny >= 0 && nx < width && ny < height)
+                .filter(|n| !defective.contains(n))
+                .fold((0u32, 0u32), |(sum, count), (nx, ny)| {
+                    (sum + mosaic[(ny * width + nx) as usize] as u32, count + 1)
+                });
+            if let Some(mean) = (sum + count / 2).checked_div(count) {
+                mosaic[(y * width + x) as usize] = mean as u16;
+            }
+        }
+    }
+}
+
+// The 8 pixels `step` away around (x, y).
+fn neighbours(x: i64, y: i64, step: i64) -> impl Iterator<Item = (i64, i64)> {
+    [-1, 0, 1]
+        .into_iter()
+        .flat_map(move |dy| [-1, 0, 1].into_iter().map(move |dx| (dx, dy)))
+        .filter(|&d| d != (0, 0))
+        .map(move |(dx, dy)| (x + dx * step, y + dy * step))
+}
+
+/// Finds defects from the per pixel temporal `mean` and standard deviation `std` of normalised
+/// frames, against the median of the same colour neighbours. Hot pixels sit more than
+/// `threshold` above them, dead ones reach less than `dead_ratio` of neighbours that are at least
+/// `threshold` above black, stuck ones don't change while their neighbours do.
+pub fn find_defects(
+    mean: &[f32],
+    std: &[f32],
+    width: usize,
+    cfa: bool,
+    threshold: f32,
+    dead_ratio: f32,
+) -> Vec<(u32, u32, DefectKind)> {
+    let height = mean.len() / width.max(1);
+    let step = if cfa { 2 } else { 1 };
+    let mut defects = Vec::new();
+    let mut around = Vec::with_capacity(8);
+    let mut around_std = Vec::with_capacity(8);
+    for y in 0..height {
+        for x in 0..width {
+            around.clear();
+            around_std.clear();
+            for (nx, ny) in neighbours(x as i64, y as i64, step) {
+                if nx >= 0 && ny >= 0 && (nx as usize) < width && (ny as usize) < height {
+                    let n = ny as usize * width + nx as usize;
+                    around.push(mean[n]);
+                    around_std.push(std[n]);
+                }
+            }
+            if around.is_empty() {
+                continue;
+            }
+            let i = y * width + x;
+            let level = median(&mut around);
+            let kind = if mean[i] - level > threshold {
+                Some(DefectKind::Hot)
+            } else if level >= threshold && mean[i] < level * dead_ratio {
+                Some(DefectKind::Dead)
+            } else if std[i] == 0.0 && median(&mut around_std) > 0.0 {
+                Some(DefectKind::Stuck)
+            } else {
+                None
+            };
+            if let Some(kind) = kind {
+                defects.push((x as u32, y as u32, kind));
+            }
+        }
+    }
+    defects
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_unstable_by(f32::total_cmp);
+    let mid = values.len() / 2;
+    match values.len() % 2 {
+        1 => values[mid],
+        _ => (values[mid - 1] + values[mid]) / 2.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFile;
+
+    #[test]
+    fn test_find_and_correct() {
+        let mut mean = vec![0.5f32; 36];
+        let mut std = vec![0.01f32; 36];
+        mean[7] = 1.0;
+        mean[20] = 0.05;
+        std[28] = 0.0;
+        let defects = find_defects(&mean, &std, 6, true, 0.1, 0.3);
+        assert_eq!(
+            defects,
+            vec![
+                (1, 1, DefectKind::Hot),
+                (2, 3, DefectKind::Dead),
+                (4, 4, DefectKind::Stuck)
+            ]
+        );
+
+        // Filled in from the same colour, two pixels away.
+        let map = DefectMap::new(6, 6, defects).unwrap();
+        let mut mosaic: Vec<u16> = (0..36)
+            .map(|i| if i % 2 == 0 { 100 } else { 300 })
+            .collect();
+        mosaic[7] = 4000;
+        map.correct(&mut mosaic, true);
+        assert_eq!(mosaic[7], 300);
+        assert_eq!(mosaic[20], 100);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let file = TempFile::new("cine_py_defects.txt");
+        let path = file.path();
+        let map = DefectMap::new(
+            8,
+            4,
+            vec![(1, 2, DefectKind::Hot), (7, 3, DefectKind::Dead)],
+        )
+        .unwrap();
+        map.save(path).unwrap();
+        assert_eq!(DefectMap::load(path).unwrap(), map);
+
+        std::fs::write(path, "# cine_py defect map 8x4\n1,2,warm\n").unwrap();
+        assert!(DefectMap::load(path).is_err());
+        assert!(DefectMap::new(8, 4, vec![(8, 0, DefectKind::Hot)]).is_err());
+    }
+}
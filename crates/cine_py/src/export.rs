@@ -0,0 +1,289 @@
+// Lossless image sequence export; 16-bit TIFF of the processed frames, DNG of the raw mosaic
+// and float OpenEXR of the linearised frames.
+// DNG tags follow the "Digital Negative Specification Version 1.4.0.0".
+
+use crate::cine;
+use crate::color::{self, Matrix3};
+use crate::conversions::ColorFilterArray;
+use crate::decompress::Decompression;
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec,
+    WritableImage, f16,
+};
+use std::io::{Seek, Write};
+use tiff::TiffResult;
+use tiff::encoder::{Rational, SRational, TiffEncoder, colortype};
+use tiff::tags::Tag;
+
+const CFA_REPEAT_PATTERN_DIM: u16 = 33421;
+const CFA_PATTERN: u16 = 33422;
+const DNG_VERSION: u16 = 50706;
+const DNG_BACKWARD_VERSION: u16 = 50707;
+const UNIQUE_CAMERA_MODEL: u16 = 50708;
+const CFA_PLANE_COLOR: u16 = 50710;
+const CFA_LAYOUT: u16 = 50711;
+const LINEARIZATION_TABLE: u16 = 50712;
+const BLACK_LEVEL: u16 = 50714;
+const WHITE_LEVEL: u16 = 50717;
+const COLOR_MATRIX_1: u16 = 50721;
+const AS_SHOT_NEUTRAL: u16 = 50728;
+const CALIBRATION_ILLUMINANT_1: u16 = 50778;
+
+const PHOTOMETRIC_CFA: u16 = 32803;
+const PHOTOMETRIC_LINEAR_RAW: u16 = 34892;
+const ILLUMINANT_D65: u16 = 21;
+
+// Fixed point denominator used for the (S)RATIONAL tags.
+const RATIONAL_SCALE: f32 = 10_000.0;
+
+/// Fills the `{frame}` (or zero padded `{frame:06}`) placeholder in `pattern` with an image number.
+pub fn frame_path(pattern: &str, image_no: i32) -> Option<String> {
+    let start = pattern.find("{frame")?;
+    let end = start + pattern[start..].find('}')?;
+    let spec = &pattern[start + "{frame".len()..end];
+    let number = match spec.strip_prefix(":0") {
+        Some(width) => format!("{:0width$}", image_no, width = width.parse().ok()?),
+        None if spec.is_empty() => image_no.to_string(),
+        None => return None,
+    };
+    Some(format!(
+        "{}{}{}",
+        &pattern[..start],
+        number,
+        &pattern[end + 1..]
+    ))
+}
+
+/// Writes an unprocessed sensor mosaic as a DNG. The CFA pattern, black/white levels, white
+/// balance and `cmCalib` (taken as camera RGB to linear Rec.709) are carried over as DNG tags.
+/// Values stay as stored; for `compression`s stored on a curve the curve goes in as the
+/// LinearizationTable and the levels are given in linear units, as DNG applies them after it.
+pub fn write_dng<W: Write + Seek>(
+    writer: W,
+    mosaic: &[u16],
+    width: u32,
+    height: u32,
+    setup: &cine::Setup,
+    cfa: &ColorFilterArray,
+    compression: &Decompression,
+) -> TiffResult<()> {
+    let mut tiff = TiffEncoder::new(writer)?;
+    let mut image = tiff.new_image::<colortype::Gray16>(width, height)?;
+    let model = match setup.camera_model() {
+        model if model.is_empty() => format!("Phantom {}", setup.Serial),
+        model => model,
+    };
+
+    let dir = image.encoder();
+    dir.write_tag(Tag::NewSubfileType, 0u32)?;
+    dir.write_tag(Tag::Make, "Vision Research")?;
+    dir.write_tag(Tag::Model, model.as_str())?;
+    dir.write_tag(Tag::Software, "cine_py")?;
+    dir.write_tag(Tag::Unknown(DNG_VERSION), &[1u8, 4, 0, 0][..])?;
+    dir.write_tag(Tag::Unknown(DNG_BACKWARD_VERSION), &[1u8, 1, 0, 0][..])?;
+    dir.write_tag(Tag::Unknown(UNIQUE_CAMERA_MODEL), model.as_str())?;
+    if let Some(table) = compression.linearisation_table() {
+        dir.write_tag(Tag::Unknown(LINEARIZATION_TABLE), table)?;
+    }
+    let linear = |level: u32| compression.to_linear(level.min(u16::MAX as u32) as u16) as u32;
+    dir.write_tag(Tag::Unknown(BLACK_LEVEL), linear(setup.black_level()))?;
+    dir.write_tag(Tag::Unknown(WHITE_LEVEL), linear(setup.white_level()))?;
+
+    match cfa.pattern() {
+        Some(pattern) => {
+            dir.write_tag(Tag::PhotometricInterpretation, PHOTOMETRIC_CFA)?;
+            dir.write_tag(Tag::Unknown(CFA_REPEAT_PATTERN_DIM), &[2u16, 2][..])?;
+            dir.write_tag(Tag::Unknown(CFA_PATTERN), &pattern[..])?;
+            dir.write_tag(Tag::Unknown(CFA_PLANE_COLOR), &[0u8, 1, 2][..])?;
+            dir.write_tag(Tag::Unknown(CFA_LAYOUT), 1u16)?;
+
+            let color_matrix: Vec<SRational> = xyz_to_camera(setup)
+                .iter()
+                .map(|v| SRational {
+                    n: (v * RATIONAL_SCALE).round() as i32,
+                    d: RATIONAL_SCALE as i32,
+                })
+                .collect();
+            dir.write_tag(Tag::Unknown(COLOR_MATRIX_1), &color_matrix[..])?;
+            dir.write_tag(Tag::Unknown(CALIBRATION_ILLUMINANT_1), ILLUMINANT_D65)?;
+
+            let neutral: Vec<Rational> = as_shot_neutral(setup)
+                .iter()
+                .map(|v| Rational {
+                    n: (v * RATIONAL_SCALE).round() as u32,
+                    d: RATIONAL_SCALE as u32,
+                })
+                .collect();
+            dir.write_tag(Tag::Unknown(AS_SHOT_NEUTRAL), &neutral[..])?;
+        }
+        None => dir.write_tag(Tag::PhotometricInterpretation, PHOTOMETRIC_LINEAR_RAW)?,
+    }
+
+    image.write_data(mosaic)
+}
+
+// DNG's ColorMatrix1 maps XYZ to camera RGB, the inverse of camera -> Rec.709 -> XYZ.
+fn xyz_to_camera(setup: &cine::Setup) -> Matrix3 {
+    let camera_to_rec709 = color::setup_matrix(&setup.cmCalib).unwrap_or(color::IDENTITY);
+    let camera_to_xyz = color::mat3_mul(&color::SRGB_TO_XYZ, &camera_to_rec709);
+    color::mat3_inverse(&camera_to_xyz)
+        .or_else(|| color::mat3_inverse(&color::SRGB_TO_XYZ))
+        .unwrap()
+}
+
+// The camera's response to a neutral surface, the reciprocal of the white balance gains.
+fn as_shot_neutral(setup: &cine::Setup) -> [f32; 3] {
+    color::white_balance_gains(setup).map(|gain| 1.0 / gain)
+}
+
+/// Writes linear float pixels (1 channel "Y" or interleaved RGB) as an OpenEXR file, stored as
+/// half or full floats.
+pub fn write_exr(
+    path: &str,
+    pixels: &[f32],
+    width: usize,
+    height: usize,
+    channels: usize,
+    half: bool,
+) -> exr::error::UnitResult {
+    let names: &[&str] = if channels == 1 {
+        &["Y"]
+    } else {
+        &["R", "G", "B"]
+    };
+    let list: SmallVec<[AnyChannel<FlatSamples>; 4]> = names
+        .iter()
+        .enumerate()
+        .map(|(c, name)| {
+            let plane = pixels.iter().skip(c).step_by(channels).copied();
+            let samples = if half {
+                FlatSamples::F16(plane.map(f16::from_f32).collect())
+            } else {
+                FlatSamples::F32(plane.collect())
+            };
+            AnyChannel::new(*name, samples)
+        })
+        .collect();
+
+    let layer = Layer::new(
+        (width, height),
+        LayerAttributes::default(),
+        Encoding::SMALL_LOSSLESS,
+        AnyChannels::sort(list),
+    );
+    Image::from_layer(layer).write().to_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lut::LUT_10_TO_12;
+    use crate::testing::TempFile;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_frame_path() {
+        assert_eq!(frame_path("out/f_{frame}.tif", 12).unwrap(), "out/f_12.tif");
+        assert_eq!(frame_path("f_{frame:06}.dng", 12).unwrap(), "f_000012.dng");
+        assert_eq!(frame_path("f_{frame:04}.dng", -3).unwrap(), "f_-003.dng");
+        assert!(frame_path("f.dng", 12).is_none());
+    }
+
+    #[test]
+    fn test_exr_round_trip() {
+        let file = TempFile::new("cine_py_export_test.exr");
+        let path = file.path();
+        let pixels: Vec<f32> = (0..12).map(|v| v as f32 * 0.25).collect();
+        write_exr(path, &pixels, 2, 2, 3, true).unwrap();
+
+        let image = exr::prelude::read_first_flat_layer_from_file(path).unwrap();
+        let channels = &image.layer_data.channel_data.list;
+        assert_eq!(channels.len(), 3);
+        // Channels are sorted by name, B comes first.
+        let blue: Vec<f32> = channels[0].sample_data.values_as_f32().collect();
+        assert_eq!(blue, vec![0.5, 1.25, 2.0, 2.75]);
+    }
+
+    // The tiff decoder refuses CFA images, so read the IFD entries (tag -> inline value) directly.
+    fn ifd_entries(bytes: &[u8]) -> HashMap<u16, [u8; 4]> {
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let ifd = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        (0..u16_at(ifd) as usize)
+            .map(|e| ifd + 2 + e * 12)
+            .map(|entry| {
+                (
+                    u16_at(entry),
+                    bytes[entry + 8..entry + 12].try_into().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dng_tags() {
+        let mut setup = cine::Setup::new(4, 2, 12, 1000);
+        setup.BlackLevel = 64;
+        let mosaic: Vec<u16> = (0..8).collect();
+        let mut bytes = Cursor::new(Vec::new());
+        let (cfa, compression) = (ColorFilterArray::Bayer, Decompression::Packed12Bit);
+        write_dng(&mut bytes, &mosaic, 4, 2, &setup, &cfa, &compression).unwrap();
+
+        let entries = ifd_entries(bytes.get_ref());
+        let photometric = entries[&Tag::PhotometricInterpretation.to_u16()];
+        assert_eq!(
+            u16::from_le_bytes([photometric[0], photometric[1]]),
+            PHOTOMETRIC_CFA
+        );
+        assert_eq!(entries[&CFA_PATTERN], [1, 2, 0, 1]);
+        assert_eq!(u32::from_le_bytes(entries[&BLACK_LEVEL]), 64);
+        assert_eq!(u32::from_le_bytes(entries[&WHITE_LEVEL]), 4095);
+        assert!(entries.contains_key(&COLOR_MATRIX_1));
+        assert!(!entries.contains_key(&LINEARIZATION_TABLE));
+    }
+
+    #[test]
+    fn test_10bit_dng_linearisation() {
+        let mut setup = cine::Setup::new(4, 2, 10, 1000);
+        setup.BlackLevel = 64;
+        let mosaic: Vec<u16> = (0..8).collect();
+        let mut bytes = Cursor::new(Vec::new());
+        let (cfa, compression) = (ColorFilterArray::Bayer, Decompression::Packed10Bit);
+        write_dng(&mut bytes, &mosaic, 4, 2, &setup, &cfa, &compression).unwrap();
+
+        // Levels are in the linear units the table maps to.
+        let entries = ifd_entries(bytes.get_ref());
+        assert_eq!(
+            u32::from_le_bytes(entries[&BLACK_LEVEL]),
+            LUT_10_TO_12[64] as u32
+        );
+        assert_eq!(
+            u32::from_le_bytes(entries[&WHITE_LEVEL]),
+            LUT_10_TO_12[1023] as u32
+        );
+        let offset = u32::from_le_bytes(entries[&LINEARIZATION_TABLE]) as usize;
+        let table: Vec<u16> = bytes.get_ref()[offset..offset + 2048]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        assert_eq!(table, LUT_10_TO_12);
+    }
+
+    #[test]
+    fn test_gray_dng_pixels() {
+        let setup = cine::Setup::new(4, 2, 10, 1000);
+        let mosaic: Vec<u16> = (0..8).collect();
+        let mut bytes = Cursor::new(Vec::new());
+        let (cfa, compression) = (ColorFilterArray::Gray, Decompression::Packed10Bit);
+        write_dng(&mut bytes, &mosaic, 4, 2, &setup, &cfa, &compression).unwrap();
+
+        let entries = ifd_entries(bytes.get_ref());
+        assert!(!entries.contains_key(&CFA_PATTERN));
+        let strip = u32::from_le_bytes(entries[&Tag::StripOffsets.to_u16()]) as usize;
+        let pixels: Vec<u16> = bytes.get_ref()[strip..strip + 16]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        assert_eq!(pixels, mosaic);
+    }
+}
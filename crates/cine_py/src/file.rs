@@ -1,15 +1,37 @@
+use crate::cache::{FrameCache, ReadAhead};
+use crate::calibration::{self, CalibrationFrame, Stack, StackMethod};
 use crate::cine;
-use crate::conversions::{CFAType, ColorFilterArray, apply_gamma};
+use crate::color::{ColorCorrection, ColorSpace};
+use crate::conversions::{ColorFilterArray, demosaic_bilinear, demosaic_half, linearise};
 use crate::decompress::Decompression;
+use crate::defects::{self, DefectMap};
+use crate::export;
+use crate::geometry::{Geometry, ResizeFilter, Sample};
+use crate::pipeline::{Pipeline, quantise};
+use crate::preview::{self, PreviewFormat};
+use crate::render::DisplayRender;
+use crate::stats::{FrameStats, FrameSummary, Reducer};
+use crate::video::{self, AviWriter};
+use crate::writer::CineWriter;
 use base64::{Engine as _, engine::general_purpose};
-use image::{Frame, ImageBuffer, ImageFormat, Luma, Rgb, RgbImage};
+use image::imageops;
+use image::{DynamicImage, GrayImage, ImageBuffer, ImageFormat, Luma, Rgb, Rgb32FImage, RgbImage};
+use pyo3::IntoPyObjectExt;
 use pyo3::PyErr;
+use pyo3::buffer::{Element, PyBuffer};
+use pyo3::exceptions::{PyAttributeError, PyIOError, PyUserWarning, PyValueError};
 use pyo3::prelude::*;
-use std::borrow::Cow;
-use std::fmt::Error;
+use rayon::prelude::*;
+use std::ffi::CString;
 use std::fs::File;
-use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::io::{self, BufWriter, Cursor, Read, Seek, SeekFrom};
 use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Memory the running totals of a parallel reduction may take together.
+const REDUCER_BUDGET: usize = 512 << 20;
 
 #[pyclass(module = "cinepy", name = "CineFile")]
 pub struct CineFile {
@@ -20,176 +42,1417 @@ pub struct CineFile {
     pub bitmap_info_header: cine::BitmapInfoHeader,
     #[pyo3(get)]
     pub setup: cine::Setup,
+    // Number of setup bytes actually stored in the file, see `Setup.Length`.
+    #[pyo3(get)]
+    pub setup_length: usize,
+    #[pyo3(get)]
+    pub setup_revision: cine::SetupRevision,
+    // False when `Setup.Length` was implausible and the layout came from the file version.
+    setup_length_trusted: bool,
+    pub(crate) tagged_blocks: Vec<cine::TaggedBlock>,
     p_images: Vec<i64>,
-    compression_type: Decompression,
-    cfa: ColorFilterArray,
+    pub(crate) compression_type: Decompression,
+    pub(crate) cfa: ColorFilterArray,
+    // Replace the setup's display processing and orientation when set from Python.
+    display_override: Option<DisplayRender>,
+    geometry_override: Option<Geometry>,
+    // Replaces the built-in processing of `get_frame` when set.
+    pipeline_override: Option<Pipeline>,
+    // Pixels filled in from their neighbours as frames are decoded.
+    pub(crate) defect_map: Option<DefectMap>,
+    path: String,
+    cache: Option<FrameCache>,
+    read_ahead: Option<ReadAhead>,
+    // Last frame `get_frame` returned, read-ahead follows the direction of travel.
+    last_frame: Option<usize>,
+    // Reused between frames by `get_frame_into`.
+    packed: Vec<u8>,
+    sensor: Vec<u16>,
+    mosaic: Vec<f32>,
+    frame: Vec<f32>,
 }
 
 // Implimentation for reading the file and setting the header info
 #[pymethods]
 impl CineFile {
+    /// Opens a Cine file, raising IOError when it can't be read and ValueError for a compression
+    /// or CFA this crate doesn't know. Warns when the setup's `Length` can't be right and the
+    /// layout was chosen from the file version instead.
     #[new]
-    pub fn new(path: &str) -> Self {
-        let mut file = File::open(path).unwrap();
+    fn py_new(py: Python<'_>, path: &str) -> PyResult<Self> {
+        let cine_file = Self::new(path)?;
+        if !cine_file.setup_length_trusted {
+            let message = format!(
+                "Setup Length {} doesn't fit the file, reading the {:?} layout of version {} files. \
+                 Missing fields read as zero, see missing_setup_fields()",
+                cine_file.setup.Length,
+                cine_file.setup_revision,
+                cine_file.cine_file_header.version
+            );
+            PyErr::warn(
+                py,
+                &py.get_type::<PyUserWarning>(),
+                &CString::new(message)?,
+                1,
+            )?;
+        }
+        Ok(cine_file)
+    }
+
+    /// Whether a setup field was stored in this file, older layouts stop before the newer fields.
+    pub fn setup_field_present(&self, name: &str) -> PyResult<bool> {
+        cine::Setup::field_within(name, self.setup_length)
+            .ok_or_else(|| PyAttributeError::new_err(format!("Setup has no field {name}")))
+    }
+
+    /// The value of a setup field, or None if this file's layout doesn't contain it.
+    pub fn setup_value(&self, py: Python<'_>, name: &str) -> PyResult<Option<PyObject>> {
+        if !self.setup_field_present(name)? {
+            return Ok(None);
+        }
+        let setup = Bound::new(py, self.setup)?;
+        Ok(Some(setup.getattr(name)?.unbind()))
+    }
+
+    pub fn missing_setup_fields(&self) -> Vec<&'static str> {
+        cine::Setup::PACKED_LAYOUT
+            .iter()
+            .filter(|(_, offset, size)| offset + size > self.setup_length)
+            .map(|(name, _, _)| *name)
+            .collect()
+    }
+
+    /// Time the frame was captured, if the file has a time block.
+    pub fn get_frame_time(&self, frame_no: usize) -> Option<cine::Time64> {
+        self.tagged_block(cine::TAG_TIME_ONLY)
+            .and_then(|block| block.time(frame_no))
+    }
+
+    /// Exposure of the frame in 1/2^32 second units, if the file has an exposure block.
+    pub fn get_frame_exposure(&self, frame_no: usize) -> Option<u32> {
+        self.tagged_block(cine::TAG_EXPOSURE_ONLY)
+            .and_then(|block| block.exposure(frame_no))
+    }
+
+    /// Copies frames `start..end` (end exclusive) into a new Cine file without decoding them.
+    /// Frame numbering, offsets and the per image tagged blocks are rewritten for the new range.
+    pub fn trim(&mut self, start: usize, end: usize, out_path: &str) -> PyResult<()> {
+        self.check_range(start, end)?;
+        let image_count = self.cine_file_header.image_count as usize;
+
+        let mut writer = CineWriter::new(
+            out_path,
+            self.setup,
+            (end - start) as u32,
+            self.compression_type.bit_depth(),
+            self.cine_file_header.first_image_no + start as i32,
+            Some(self.cine_file_header.trigger_time),
+        )?;
+        for block in &self.tagged_blocks {
+            if block.block_type != cine::TAG_TIME_ONLY
+                && block.block_type != cine::TAG_EXPOSURE_ONLY
+            {
+                let block = block.slice_images(image_count, start, end);
+                writer.add_tagged_block(block.block_type, block.data)?;
+            }
+        }
+        for frame_no in start..end {
+            let (annotation, pixels) = self.read_annotated_image(frame_no)?;
+            writer.write_raw_frame(
+                &pixels,
+                annotation,
+                self.get_frame_time(frame_no),
+                self.get_frame_exposure(frame_no),
+            )?;
+        }
+        writer.finish()
+    }
+
+    /// Writes frames `start..end` as 16-bit TIFFs of the processed frame, grey or RGB as
+    /// `frame_shape()` has it. `pattern` is the output path with `{frame}` or `{frame:06}` standing
+    /// in for the image number. Returns the paths.
+    pub fn export_tiff_sequence(
+        &mut self,
+        start: usize,
+        end: usize,
+        pattern: &str,
+    ) -> PyResult<Vec<String>> {
+        self.check_range(start, end)?;
+        let mut paths = Vec::with_capacity(end - start);
+        for frame_no in start..end {
+            let path = self.frame_path(pattern, frame_no)?;
+            self.frame_image(frame_no as i32)?
+                .save_with_format(&path, ImageFormat::Tiff)
+                .map_err(|e| PyIOError::new_err(e.to_string()))?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// Writes frames `start..end` as DNGs of the unprocessed sensor data, keeping the CFA
+    /// pattern, levels and colour calibration as tags. `pattern` is as for `export_tiff_sequence`.
+    pub fn export_dng_sequence(
+        &mut self,
+        start: usize,
+        end: usize,
+        pattern: &str,
+    ) -> PyResult<Vec<String>> {
+        self.check_range(start, end)?;
+        let width: u32 = self.bitmap_info_header.bi_width as u32;
+        let height: u32 = self.bitmap_info_header.bi_height as u32;
+        let mut paths = Vec::with_capacity(end - start);
+        for frame_no in start..end {
+            let path = self.frame_path(pattern, frame_no)?;
+            let mosaic = self.get_sensor_frame(frame_no)?;
+            let out = BufWriter::new(File::create(&path)?);
+            export::write_dng(
+                out,
+                &mosaic,
+                width,
+                height,
+                &self.setup,
+                &self.cfa,
+                &self.compression_type,
+            )
+            .map_err(|e| PyIOError::new_err(e.to_string()))?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// Writes a frame as an OpenEXR of linear values, black at 0.0 and white at 1.0 with nothing
+    /// clipped or tone mapped. Colour frames are demosaiced, optionally white balanced and
+    /// converted to `color_space`.
+    #[pyo3(signature = (frame_no, out_path, half=true, white_balance=false, color_space=ColorSpace::Camera))]
+    pub fn save_exr(
+        &mut self,
+        frame_no: usize,
+        out_path: &str,
+        half: bool,
+        white_balance: bool,
+        color_space: ColorSpace,
+    ) -> PyResult<()> {
+        let (width, height) = self.frame_size();
+        let correction = ColorCorrection::from_setup(&self.setup, color_space, white_balance);
+        let (pixels, channels) = self.linear_frame(frame_no, &correction)?;
+        export::write_exr(
+            out_path,
+            &pixels,
+            width as usize,
+            height as usize,
+            channels,
+            half,
+        )
+        .map_err(|e| PyIOError::new_err(e.to_string()))
+    }
+
+    /// `save_exr` for frames `start..end`, named as for `export_tiff_sequence`.
+    #[pyo3(signature = (start, end, pattern, half=true, white_balance=false, color_space=ColorSpace::Camera))]
+    pub fn export_exr_sequence(
+        &mut self,
+        start: usize,
+        end: usize,
+        pattern: &str,
+        half: bool,
+        white_balance: bool,
+        color_space: ColorSpace,
+    ) -> PyResult<Vec<String>> {
+        self.check_range(start, end)?;
+        let mut paths = Vec::with_capacity(end - start);
+        for frame_no in start..end {
+            let path = self.frame_path(pattern, frame_no)?;
+            self.save_exr(frame_no, &path, half, white_balance, color_space)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// Encodes frames `start..end` as a Motion JPEG AVI of the 8-bit display rendition. Plays back
+    /// at `fps`, or at the recorded playback rate (`Setup.fPbRate`) when not given. Raises
+    /// OSError when the file would pass the 4 GB AVI limit or frames are over 32767 pixels a side.
+    #[pyo3(signature = (start, end, out_path, fps=None, quality=90))]
+    pub fn export_video(
+        &mut self,
+        start: usize,
+        end: usize,
+        out_path: &str,
+        fps: Option<f64>,
+        quality: u8,
+    ) -> PyResult<()> {
+        self.check_range(start, end)?;
+        let fps = match fps {
+            Some(fps) if fps > 0.0 => fps,
+            Some(_) => return Err(PyValueError::new_err("fps must be positive")),
+            None => self.playback_rate(),
+        };
+        if !(1..=100).contains(&quality) {
+            return Err(PyValueError::new_err("quality must be between 1 and 100"));
+        }
+        let (width, height) = self.frame_size();
+
+        let out = BufWriter::new(File::create(out_path)?);
+        let mut avi = AviWriter::new(out, width, height, fps)?;
+        for frame_no in start..end {
+            let (pixels, channels) = self.display_frame(frame_no)?;
+            let rgb = match channels {
+                1 => video::gray_to_rgb(&pixels),
+                _ => pixels,
+            };
+            let jpeg = video::encode_jpeg(&rgb, width, height, quality)
+                .map_err(|e| PyIOError::new_err(e.to_string()))?;
+            avi.write_frame(&jpeg)?;
+        }
+        avi.finish()?;
+        Ok(())
+    }
+
+    /// A looping animated GIF or APNG of every `stride`th frame in `start..end`, scaled down so
+    /// the longest edge is at most `max_size`. The frame delay keeps the recorded playback rate
+    /// (`Setup.fPbRate`) with the skipped frames left out.
+    #[pyo3(signature = (start, end, stride=1, max_size=256, format="gif"))]
+    pub fn preview(
+        &mut self,
+        start: usize,
+        end: usize,
+        stride: usize,
+        max_size: u32,
+        format: &str,
+    ) -> PyResult<Vec<u8>> {
+        self.check_range(start, end)?;
+        let format = PreviewFormat::from_name(format)
+            .ok_or_else(|| PyValueError::new_err("format must be \"gif\" or \"apng\""))?;
+        if stride == 0 || max_size == 0 {
+            return Err(PyValueError::new_err(
+                "stride and max_size must be positive",
+            ));
+        }
+        let (width, height) = self.frame_size();
+        let (preview_width, preview_height) = preview::fit_size(width, height, max_size);
+
+        let mut frames = Vec::new();
+        for frame_no in (start..end).step_by(stride) {
+            let (pixels, channels) = self.display_frame(frame_no)?;
+            let rgb = match channels {
+                1 => video::gray_to_rgb(&pixels),
+                _ => pixels,
+            };
+            let image = RgbImage::from_vec(width, height, rgb).unwrap();
+            frames.push(preview::resize_rgb(image, preview_width, preview_height));
+        }
+        let delay = Duration::from_secs_f64(stride as f64 / self.playback_rate());
+        preview::encode_animation(&format, &frames, delay)
+            .map_err(|e| PyIOError::new_err(e.to_string()))
+    }
+
+    /// An 8-bit PNG of the frame no larger than `max_size` on its longest edge. Colour frames use a
+    /// half size demosaic and everything is area averaged down, so it's quick on full frames.
+    #[pyo3(signature = (frame_no, max_size=256))]
+    pub fn thumbnail(&mut self, frame_no: usize, max_size: u32) -> PyResult<Vec<u8>> {
+        let image = self.thumbnail_image(frame_no, max_size)?;
+        encode_png(image)
+    }
+
+    /// Tiles `n_frames` evenly spaced thumbnails, `columns` per row, into one PNG. Each tile has
+    /// its image number and time from the trigger burnt in.
+    #[pyo3(signature = (n_frames, columns, max_size=256))]
+    pub fn contact_sheet(
+        &mut self,
+        n_frames: usize,
+        columns: usize,
+        max_size: u32,
+    ) -> PyResult<Vec<u8>> {
+        let image_count = self.p_images.len();
+        if n_frames == 0 || columns == 0 || image_count == 0 {
+            return Err(PyValueError::new_err(
+                "n_frames, columns and the file's image count must be positive",
+            ));
+        }
+        let n_frames = n_frames.min(image_count);
+        let (width, height) = self.frame_size();
+        let (tile_width, tile_height) = preview::fit_size(width, height, max_size);
+        let rows = n_frames.div_ceil(columns);
+        let mut sheet = RgbImage::new(
+            tile_width * columns.min(n_frames) as u32,
+            tile_height * rows as u32,
+        );
+
+        for i in 0..n_frames {
+            let frame_no = match n_frames {
+                1 => 0,
+                _ => i * (image_count - 1) / (n_frames - 1),
+            };
+            let mut tile = self.thumbnail_image(frame_no, max_size)?;
+            // Colour thumbnails come from a half size demosaic, which can be a pixel short.
+            if tile.dimensions() != (tile_width, tile_height) {
+                tile = preview::resize_rgb(tile, tile_width, tile_height);
+            }
+            let label = format!(
+                "{} {:.6}s",
+                self.image_no(frame_no),
+                self.time_from_trigger(frame_no)
+            );
+            preview::draw_label(&mut tile, &label, (tile_width / 128).max(1));
+            let x = (i % columns) as i64 * tile_width as i64;
+            let y = (i / columns) as i64 * tile_height as i64;
+            imageops::replace(&mut sheet, &tile, x, y);
+        }
+        encode_png(sheet)
+    }
+
+    /// The frame through `pipeline()`, linear and white balanced unless a pipeline was set with
+    /// `set_pipeline` (see `rendered_pipeline` for the setup's gamma and tone curve).
+    /// `get_raw_frame` and `get_sensor_frame` return the earlier stages. `roi` (x, y, width,
+    /// height) in stored frame coordinates returns just that region, oriented but without the
+    /// geometry's crop or resample, see `get_roi`.
+    ///
+    /// `dtype="float32"` gives the pipeline's output as is, black 0.0 / white 1.0. `uint16` scales
+    /// that to the full 16 bits, clipped. It used to be the stored values shifted up to 16 bits,
+    /// `get_sensor_frame` still gives those unshifted.
+    #[pyo3(name = "get_frame", signature = (frame_no, roi=None, dtype="uint16"))]
+    pub fn py_get_frame(
+        &mut self,
+        py: Python<'_>,
+        frame_no: i32,
+        roi: Option<(u32, u32, u32, u32)>,
+        dtype: &str,
+    ) -> PyResult<PyObject> {
+        match dtype {
+            "uint16" => self.get_frame(frame_no, roi)?.into_py_any(py),
+            "float32" => self.get_frame_f32(frame_no as usize, roi)?.into_py_any(py),
+            _ => Err(PyValueError::new_err(
+                "dtype must be \"uint16\" or \"float32\"",
+            )),
+        }
+    }
+
+    /// The frame's pixel data as stored, still packed. See `get_sensor_frame` for the values.
+    pub fn get_raw_frame(&mut self, frame_no: usize) -> PyResult<Vec<u8>> {
+        let mut packed = Vec::new();
+        self.read_raw_frame(frame_no, &mut packed)?;
+        Ok(packed)
+    }
+
+    /// The unpacked sensor values of a frame, the mosaic for colour sensors, before any
+    /// corrections, demosaicing or geometry.
+    pub fn get_sensor_frame(&mut self, frame_no: usize) -> PyResult<Vec<u16>> {
+        let packed = self.get_raw_frame(frame_no)?;
+        self.compression_type
+            .decompress(&packed)
+            .map_err(|_| PyValueError::new_err("Failed to unpack frame"))
+    }
+
+    /// `get_frame` into a writable, C-contiguous uint16 or float32 buffer of `frame_shape()`,
+    /// such as a NumPy array, so loops over many frames can reuse one array. The buffer's type
+    /// picks the `dtype`. Nothing is allocated per frame unless the cache is on or the geometry
+    /// changes the frame.
+    #[pyo3(name = "get_frame_into")]
+    pub fn py_get_frame_into(&mut self, frame_no: usize, out: &Bound<'_, PyAny>) -> PyResult<()> {
+        if let Ok(buffer) = PyBuffer::<f32>::get(out) {
+            return with_writable(&buffer, |out| self.get_frame_into_f32(frame_no, out));
+        }
+        let buffer = PyBuffer::<u16>::get(out)?;
+        with_writable(&buffer, |out| self.get_frame_into(frame_no, out))
+    }
+
+    /// Shape of `get_frame` as (height, width, channels), channels being 3 for colour.
+    pub fn frame_shape(&self) -> (usize, usize, usize) {
+        let pipeline = self.pipeline();
+        let (width, height) = pipeline.geometry.output_size(
+            self.bitmap_info_header.bi_width as u32,
+            self.bitmap_info_header.bi_height as u32,
+        );
+        let channels = pipeline.channels(self.cfa.pattern());
+        (height as usize, width as usize, channels)
+    }
+
+    /// The pipeline `get_frame` runs; the one set with `set_pipeline`, otherwise the setup's
+    /// white balance and colour matrix with `geometry()`, keeping values linear.
+    pub fn pipeline(&self) -> Pipeline {
+        match &self.pipeline_override {
+            Some(pipeline) => pipeline.clone(),
+            None => {
+                Pipeline::from_setup(&self.setup, &self.compression_type, self.geometry(), false)
+            }
+        }
+    }
+
+    /// The setup's pipeline with its gamma and `fTone` curve added, for frames that look like
+    /// PCC's rather than linear ones. Pass it to `set_pipeline` to opt in.
+    pub fn rendered_pipeline(&self) -> Pipeline {
+        Pipeline::from_setup(&self.setup, &self.compression_type, self.geometry(), true)
+    }
+
+    /// Runs `get_frame` through `pipeline`, `None` goes back to the setup's. Empties the cache.
+    /// Warns when a dark frame would be subtracted from frames the camera already took a black
+    /// reference off, see `black_reference_applied`. Raises ValueError when the geometry's crop
+    /// doesn't fit in the frame.
+    #[pyo3(signature = (pipeline=None))]
+    pub fn set_pipeline(&mut self, py: Python<'_>, pipeline: Option<Pipeline>) -> PyResult<()> {
+        if let Some(pipeline) = &pipeline {
+            self.check_geometry(&pipeline.geometry)?;
+        }
+        if self.black_reference_applied()
+            && pipeline.as_ref().is_some_and(|p| p.dark_frame.is_some())
+        {
+            PyErr::warn(
+                py,
+                &py.get_type::<PyUserWarning>(),
+                c"The camera already applied a black reference (BlackCalSVer), subtracting a dark frame takes it off twice",
+                1,
+            )?;
+        }
+        self.pipeline_override = pipeline;
+        self.reset_cache();
+        Ok(())
+    }
+
+    /// Whether the camera subtracted a black reference before recording, going by `BlackCalSVer`.
+    pub fn black_reference_applied(&self) -> bool {
+        self.setup.BlackCalSVer != 0
+    }
+
+    /// Stacks frames `start..end` (end exclusive) of a recording made with the lens capped into a
+    /// dark frame of linear sensor values, for `Pipeline.dark_frame`.
+    #[pyo3(signature = (start, end, method=StackMethod::Mean))]
+    pub fn build_dark_frame(
+        &mut self,
+        start: usize,
+        end: usize,
+        method: StackMethod,
+    ) -> PyResult<CalibrationFrame> {
+        let width = self.bitmap_info_header.bi_width as u32;
+        let height = self.bitmap_info_header.bi_height as u32;
+        CalibrationFrame::new(width, height, self.stack_linear(start, end, method)?)
+    }
+
+    /// Stacks frames `start..end` of an evenly lit recording (a diffuser or integrating sphere in
+    /// front of the lens) into flat field gains for `Pipeline.flat_field`. `dark` (a dark frame
+    /// from the same settings) is taken off first, `BlackLevel` without one. Colour sensors are
+    /// evened out per colour of the mosaic. Pixels darker than `min_signal` times the mean of
+    /// their colour keep a gain of 1, the others are kept within `1/8..8`.
+    #[pyo3(signature = (start, end, dark=None, method=StackMethod::Mean, min_signal=0.1))]
+    pub fn build_flat_field(
+        &mut self,
+        start: usize,
+        end: usize,
+        dark: Option<CalibrationFrame>,
+        method: StackMethod,
+        min_signal: f32,
+    ) -> PyResult<CalibrationFrame> {
+        let width = self.bitmap_info_header.bi_width as u32;
+        let height = self.bitmap_info_header.bi_height as u32;
+        let mut flat = self.stack_linear(start, end, method)?;
+        match dark {
+            Some(dark) if dark.pixels.len() != flat.len() => {
+                return Err(PyValueError::new_err(
+                    "Dark frame doesn't match the frame size",
+                ));
+            }
+            Some(dark) => flat.iter_mut().zip(&dark.pixels).for_each(|(v, d)| *v -= d),
+            None => {
+                let black = self
+                    .compression_type
+                    .to_linear(self.setup.black_level() as u16) as f32;
+                flat.iter_mut().for_each(|v| *v -= black);
+            }
+        }
+        let gains =
+            calibration::flat_field_gains(&flat, width as usize, self.cfa.pattern(), min_signal);
+        CalibrationFrame::new(width, height, gains)
+    }
+
+    /// Saves `get_frame` as a 16-bit grey or RGB image, the format going by the extension.
+    pub fn save_single_frame(&mut self, frame_no: i32, out_path: String) -> PyResult<()> {
+        self.frame_image(frame_no)?
+            .save(out_path)
+            .map_err(|e| PyIOError::new_err(e.to_string()))
+    }
+
+    /// `save_single_frame`, grey or RGB likewise.
+    pub fn save_single_colour_frame(&mut self, frame_no: i32, out_path: String) -> PyResult<()> {
+        self.save_single_frame(frame_no, out_path)
+    }
+
+    /// The frame as linear floats, black 0.0 and white 1.0. Colour frames are demosaiced to
+    /// interleaved RGB, optionally white balanced and converted to `color_space`.
+    #[pyo3(signature = (frame_no, color_space=ColorSpace::LinearSrgb, white_balance=true))]
+    pub fn get_linear_frame(
+        &mut self,
+        frame_no: usize,
+        color_space: ColorSpace,
+        white_balance: bool,
+    ) -> PyResult<Vec<f32>> {
+        let correction = ColorCorrection::from_setup(&self.setup, color_space, white_balance);
+        Ok(self.linear_frame(frame_no, &correction)?.0)
+    }
+
+    /// The display processing used for 8-bit renders (`get_display_frame`, PNG, video and
+    /// previews). Starts out as the setup's values.
+    pub fn display_settings(&self) -> DisplayRender {
+        self.display_render()
+    }
+
+    /// Overrides the setup's display processing, `None` goes back to the setup.
+    #[pyo3(signature = (settings=None))]
+    pub fn set_display_settings(&mut self, settings: Option<DisplayRender>) {
+        self.display_override = settings;
+    }
+
+    /// The flips and rotation applied to processed frames. Starts out as the setup's `bFlipH`,
+    /// `bFlipV` and `Rotate`.
+    pub fn geometry(&self) -> Geometry {
+        self.geometry_override
+            .unwrap_or_else(|| Geometry::from_setup(&self.setup))
+    }
+
+    /// `get_frame(frame_no, roi=(x, y, width, height))`. Unless a defect map is set, only the rows
+    /// of the region are read and only its columns unpacked, so small regions are much quicker
+    /// than whole frames. Demosaiced regions read a 2x2 cell more all round.
+    pub fn get_roi(
+        &mut self,
+        frame_no: usize,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> PyResult<Vec<u16>> {
+        let region = self.frame_region(frame_no, (x, y, width, height))?;
+        Ok(region.into_iter().map(quantise).collect())
+    }
+
+    /// Keeps up to `max_bytes` of processed frames (as float32) for `get_frame`, dropping the
+    /// least recently used. With `read_ahead` the next frames in the direction of travel are
+    /// processed on a background thread, with its own handle on the file. `max_bytes=0` turns the
+    /// cache off. Changing the pipeline, geometry or defect map empties it.
+    #[pyo3(signature = (max_bytes, read_ahead=0))]
+    pub fn set_cache(&mut self, max_bytes: usize, read_ahead: usize) -> PyResult<()> {
+        self.read_ahead = None;
+        self.cache = (max_bytes > 0).then(|| FrameCache::new(max_bytes));
+        if max_bytes > 0 && read_ahead > 0 {
+            self.read_ahead = Some(ReadAhead::spawn(self.worker_handle()?, read_ahead));
+        }
+        Ok(())
+    }
+
+    /// Number of frames and bytes held by the cache.
+    pub fn cache_info(&mut self) -> (usize, usize) {
+        self.collect_read_ahead();
+        self.cache
+            .as_ref()
+            .map_or((0, 0), |cache| (cache.len(), cache.bytes()))
+    }
+
+    /// The setup's orientation together with its crop (`EnableCrop`/`CropRect`) and output size
+    /// (`EnableResample`/`ResampleWidth`/`ResampleHeight`). Pass it to `set_geometry` to get
+    /// frames framed as the operator chose.
+    #[pyo3(signature = (filter=ResizeFilter::Triangle))]
+    pub fn setup_framing(&self, filter: ResizeFilter) -> Geometry {
+        Geometry::from_setup_framing(&self.setup, filter)
+    }
+
+    /// Overrides the geometry, `None` goes back to the setup's orientation. Empties the cache.
+    /// Raises ValueError when the crop doesn't fit in the frame.
+    #[pyo3(signature = (geometry=None))]
+    pub fn set_geometry(&mut self, geometry: Option<Geometry>) -> PyResult<()> {
+        if let Some(geometry) = &geometry {
+            self.check_geometry(geometry)?;
+        }
+        self.geometry_override = geometry;
+        self.reset_cache();
+        Ok(())
+    }
+
+    /// Width and height of processed frames, after any rotation.
+    pub fn frame_size(&self) -> (u32, u32) {
+        self.geometry().output_size(
+            self.bitmap_info_header.bi_width as u32,
+            self.bitmap_info_header.bi_height as u32,
+        )
+    }
+
+    /// `get_frame_as_png`, base64 encoded.
+    #[pyo3(signature = (frame_no, raw=false))]
+    pub fn base64_png(&mut self, frame_no: i32, raw: bool) -> Result<String, PyErr> {
+        let img_png = self.get_frame_as_png(frame_no, raw)?;
+        Ok(general_purpose::STANDARD.encode(img_png))
+    }
+
+    /// The frame as an 8-bit PNG rendered with the setup's display processing, or with `raw` the
+    /// 16-bit values of `get_frame`.
+    #[pyo3(signature = (frame_no, raw=false))]
+    pub fn get_frame_as_png(&mut self, frame_no: i32, raw: bool) -> Result<Vec<u8>, PyErr> {
+        if raw {
+            return encode_png(self.frame_image(frame_no)?);
+        }
+        let (width, height) = self.frame_size();
+        let (pixels, channels) = self.display_frame(frame_no as usize)?;
+        let image = match channels {
+            1 => GrayImage::from_vec(width, height, pixels).map(DynamicImage::ImageLuma8),
+            _ => RgbImage::from_vec(width, height, pixels).map(DynamicImage::ImageRgb8),
+        };
+        encode_png(
+            image.ok_or_else(|| PyValueError::new_err("Frame doesn't match the image size"))?,
+        )
+    }
+
+    /// The frame rendered to 8 bits with the setup's display processing (`Conv8Min`/`Conv8Max`,
+    /// flare, gain, offset, pedestals, gamma and the tone curve). Colour frames are white balanced
+    /// interleaved RGB.
+    pub fn get_display_frame(&mut self, frame_no: usize) -> PyResult<Vec<u8>> {
+        Ok(self.display_frame(frame_no)?.0)
+    }
+    // fn save_single_colour_frame(&mut self, frame_no: i32, out_path: String) {
+    //     let width: u32 = self.bitmap_info_header.bi_width as u32;
+    //     let height: u32 = self.bitmap_info_header.bi_height as u32;
+    //     let rgb_pixels = CineFile::get_frame(self, frame_no, None);
+
+    //     let mut img: ImageBuffer<Rgb<u16>, Vec<u16>> = ImageBuffer::new(width, height);
+
+    //     for y in 0..height {
+    //         for x in 0..width {
+    //             let idx: usize = ((y * width + x) * 3) as usize;
+    //             let r: u16 = rgb_pixels[idx];
+    //             let g: u16 = rgb_pixels[idx + 1];
+    //             let b: u16 = rgb_pixels[idx + 2];
+    //             img.put_pixel(x, y, Rgb([r, g, b]));
+    //         }
+    //     }
+
+    //     img.save(out_path).unwrap();
+    // }
+
+    /// Per pixel mean, standard deviation, minimum and maximum of frames `start..end` through
+    /// `pipeline()` with any gamma and tone curve left out, so they're linear like
+    /// `get_frame(dtype="float32")` by default, e.g. the background of a PIV recording. Frames are
+    /// read in parallel and only the running totals are kept, by fewer threads for large frames.
+    pub fn frame_stats(&self, py: Python<'_>, start: usize, end: usize) -> PyResult<FrameStats> {
+        let (height, width, channels) = self.frame_shape();
+        let pipeline = Pipeline {
+            gamma: None,
+            tone: Vec::new(),
+            ..self.pipeline()
+        };
+        let stats = py.allow_threads(|| {
+            self.reduce_frames(start, end, width * height * channels, |handle, frame_no| {
+                Ok(pipeline.process(handle, frame_no)?.0)
+            })
+        })?;
+        Ok(stats.finish(width as u32, height as u32, channels))
+    }
+
+    /// Histograms of `bins` bins from black to white, per colour of the mosaic for colour sensors,
+    /// and the mean, maximum and saturated pixel count of frames `start..end` (all by default),
+    /// aligned with their times. Uses the linear sensor values with the defect map applied, so
+    /// saturation is against `Setup.WhiteLevel`. Frames are read in parallel.
+    #[pyo3(signature = (bins=256, start=0, end=None))]
+    pub fn frame_summary(
+        &self,
+        py: Python<'_>,
+        bins: usize,
+        start: usize,
+        end: Option<usize>,
+    ) -> PyResult<FrameSummary> {
+        if bins == 0 {
+            return Err(PyValueError::new_err("Histograms need at least one bin"));
+        }
+        let end = end.unwrap_or(self.cine_file_header.image_count as usize);
+        let width = self.bitmap_info_header.bi_width as usize;
+        let pattern = self.cfa.pattern();
+        let channels = if pattern.is_some() { 3 } else { 1 };
+        let channel_of = |i: usize| match pattern {
+            Some(pattern) => pattern[(i / width % 2) * 2 + i % width % 2] as usize,
+            None => 0,
+        };
+        let threads = rayon::current_num_threads();
+        let runs = py.allow_threads(|| {
+            self.par_runs(start, end, threads, |handle, frames| {
+                let mut summary = FrameSummary::new(bins, channels);
+                for frame_no in frames {
+                    let linear = handle.linear_mosaic(frame_no)?;
+                    summary.add(
+                        frame_no,
+                        handle.time_from_trigger(frame_no),
+                        &linear,
+                        channel_of,
+                    );
+                }
+                Ok(summary)
+            })
+        })?;
+        Ok(runs
+            .into_iter()
+            .reduce(FrameSummary::append)
+            .unwrap_or_else(|| FrameSummary::new(bins, channels)))
+    }
+
+    /// Finds hot, stuck and dead pixels from the temporal mean and standard deviation of frames
+    /// `start..end`, normalised to black 0.0 / white 1.0, compared with the neighbours of the
+    /// same colour. Hot pixels sit more than `threshold` above them, dead ones reach less than
+    /// `dead_ratio` of neighbours at least `threshold` above black, stuck ones never change while
+    /// their neighbours do. Use a few dozen frames with some light and motion for all three.
+    /// Frames are read in parallel.
+    #[pyo3(signature = (start, end, threshold=0.1, dead_ratio=0.3))]
+    pub fn detect_defects(
+        &self,
+        py: Python<'_>,
+        start: usize,
+        end: usize,
+        threshold: f32,
+        dead_ratio: f32,
+    ) -> PyResult<DefectMap> {
+        let width = self.bitmap_info_header.bi_width as u32;
+        let height = self.bitmap_info_header.bi_height as u32;
+        let pixel_count = width as usize * height as usize;
+        let found = py.allow_threads(|| {
+            let stats = self.reduce_frames(start, end, pixel_count, |handle, frame_no| {
+                // As stored, a defect map already set mustn't hide anything.
+                Ok(linearise(
+                    &handle.get_sensor_frame(frame_no)?,
+                    &handle.compression_type,
+                    handle.setup.black_level(),
+                    handle.setup.white_level(),
+                ))
+            })?;
+            Ok::<_, PyErr>(defects::find_defects(
+                &stats.mean(),
+                &stats.std(),
+                width as usize,
+                self.cfa.pattern().is_some(),
+                threshold,
+                dead_ratio,
+            ))
+        })?;
+        DefectMap::new(width, height, found)
+    }
+
+    pub fn defect_map(&self) -> Option<DefectMap> {
+        self.defect_map.clone()
+    }
+
+    /// Fills in the map's pixels from their neighbours of the same colour whenever frames are
+    /// decoded, for `get_frame` and pipelines alike. `get_raw_frame` and `get_sensor_frame` stay
+    /// as stored. `None` turns it off. Empties the cache.
+    #[pyo3(signature = (defect_map=None))]
+    pub fn set_defect_map(&mut self, defect_map: Option<DefectMap>) -> PyResult<()> {
+        let (width, height) = (
+            self.bitmap_info_header.bi_width as u32,
+            self.bitmap_info_header.bi_height as u32,
+        );
+        if let Some(map) = &defect_map
+            && (map.width, map.height) != (width, height)
+        {
+            return Err(PyValueError::new_err(format!(
+                "Defect map is {}x{}, frames are {width}x{height}",
+                map.width, map.height
+            )));
+        }
+        self.defect_map = defect_map;
+        self.reset_cache();
+        Ok(())
+    }
+}
+
+impl CineFile {
+    /// Opens a Cine file, see `py_new`.
+    pub fn new(path: &str) -> PyResult<Self> {
+        let mut file = File::open(path)?;
         // Read CINEFILEHEADER
-        let cine_file_header: cine::CineFileHeader = read_structs(&mut file).unwrap();
+        let cine_file_header: cine::CineFileHeader = read_structs(&mut file)?;
 
         // Read BITMAPINFOHEADER
-        file.seek(SeekFrom::Start(cine_file_header.offset_image_header as u64))
-            .unwrap();
-        let bitmap_info_header: cine::BitmapInfoHeader = read_structs(&mut file).unwrap();
+        file.seek(SeekFrom::Start(cine_file_header.offset_image_header as u64))?;
+        let bitmap_info_header: cine::BitmapInfoHeader = read_structs(&mut file)?;
 
         // Read SETUP
-        file.seek(SeekFrom::Start(cine_file_header.offset_setup as u64))
-            .unwrap();
-        let packed_setup: cine::PackedSetup = read_structs(&mut file).unwrap();
+        file.seek(SeekFrom::Start(cine_file_header.offset_setup as u64))?;
+        let space = cine_file_header
+            .offset_image_offsets
+            .saturating_sub(cine_file_header.offset_setup) as usize;
+        let fallback = cine::SetupRevision::from_version(cine_file_header.version);
+        let (packed_setup, setup_length, setup_length_trusted) =
+            read_setup(&mut file, space, fallback)?;
         let setup: cine::Setup = cine::Setup::from(packed_setup);
 
+        // Read the tagged blocks between the stored setup and the frame offsets
+        let setup_end = match setup_length_trusted {
+            true => setup.Length as usize,
+            false => setup_length,
+        };
+        let tagged_start = cine_file_header.offset_setup as u64 + setup_end as u64;
+        let tagged_blocks = read_tagged_blocks(
+            &mut file,
+            tagged_start,
+            cine_file_header.offset_image_offsets as u64,
+        )?;
+
         // Read frame offsets
         let image_count = cine_file_header.image_count as usize;
         file.seek(SeekFrom::Start(
             cine_file_header.offset_image_offsets as u64,
-        ))
-        .unwrap();
+        ))?;
 
         let total_bytes = image_count
             .checked_mul(std::mem::size_of::<i64>())
-            .expect("Image count is too large");
+            .ok_or_else(|| PyValueError::new_err("Image count is too large"))?;
 
         let mut buffer = vec![0u8; total_bytes];
-        file.read_exact(&mut buffer).unwrap();
+        file.read_exact(&mut buffer)?;
 
         let p_images: Vec<i64> = buffer
             .chunks_exact(8)
             .map(|chunk| i64::from_le_bytes(chunk.try_into().unwrap()))
             .collect();
 
-        Self {
+        let compression_type = Decompression::get_decompression_type(
+            &bitmap_info_header.bi_compression,
+        )
+        .map_err(|_| {
+            PyValueError::new_err(format!(
+                "Unknown compression {}",
+                bitmap_info_header.bi_compression
+            ))
+        })?;
+        let cfa = ColorFilterArray::get_cfa(&setup.CFA)
+            .map_err(|_| PyValueError::new_err(format!("Unknown CFA {:#x}", setup.CFA)))?;
+
+        Ok(Self {
             file,
             cine_file_header,
             bitmap_info_header,
             setup,
+            setup_length,
+            setup_revision: cine::SetupRevision::from_length(setup_length),
+            setup_length_trusted,
+            tagged_blocks,
             p_images,
-            compression_type: Decompression::get_decompression_type(
-                &bitmap_info_header.bi_compression,
-            )
-            .unwrap(),
-            cfa: ColorFilterArray::get_cfa(&setup.CFA).unwrap(),
+            compression_type,
+            cfa,
+            display_override: None,
+            geometry_override: None,
+            pipeline_override: None,
+            defect_map: None,
+            path: path.to_string(),
+            cache: None,
+            read_ahead: None,
+            last_frame: None,
+            packed: Vec::new(),
+            sensor: Vec::new(),
+            mosaic: Vec::new(),
+            frame: Vec::new(),
+        })
+    }
+
+    fn check_geometry(&self, geometry: &Geometry) -> PyResult<()> {
+        geometry.check_frame(
+            self.bitmap_info_header.bi_width as u32,
+            self.bitmap_info_header.bi_height as u32,
+        )
+    }
+
+    pub fn tagged_block(&self, block_type: u16) -> Option<&cine::TaggedBlock> {
+        self.tagged_blocks
+            .iter()
+            .find(|block| block.block_type == block_type)
+    }
+
+    /// Checks `start..end` is a non-empty range of frames in this file.
+    pub fn check_range(&self, start: usize, end: usize) -> PyResult<()> {
+        let image_count = self.cine_file_header.image_count as usize;
+        if start >= end || end > image_count {
+            return Err(PyValueError::new_err(format!(
+                "Invalid frame range {start}..{end} for {image_count} frames"
+            )));
         }
+        Ok(())
     }
 
-    pub fn get_frame(&mut self, frame_no: i32) -> Result<Vec<u16>, PyErr> {
-        // TODO: Split this into two functions 1) gets the raw bytes, 2) applies corrections.
+    /// The image number Phantom software shows for a frame, counted from the trigger.
+    pub fn image_no(&self, frame_no: usize) -> i32 {
+        self.cine_file_header.first_image_no + frame_no as i32
+    }
 
-        // TODO: Turn this into proper error handling
-        // Check request frame actually exists, rq if it doesnt.
-        // if frame_no >= self.cine_file_header.image_count as i32 {
-        //     return PyErr;
-        // }
+    fn frame_path(&self, pattern: &str, frame_no: usize) -> PyResult<String> {
+        export::frame_path(pattern, self.image_no(frame_no)).ok_or_else(|| {
+            PyValueError::new_err("Pattern needs a {frame} or {frame:06} style placeholder")
+        })
+    }
 
-        let pixel_buffer_size: u32 = self.bitmap_info_header.bi_size_image;
-        // get the start byte of the image requesteds annotations
-        let annotations_loc: i64 = self.p_images[frame_no as usize];
-        // Get the size of the annotations so we can skip it and get to the start of the pixel location
-        self.file
-            .seek(SeekFrom::Start(annotations_loc as u64))
-            .unwrap();
-        let mut anno_offset_buf = [0u8; 4];
-        self.file.read_exact(&mut anno_offset_buf).unwrap();
-        let offset_to_pixels = u32::from_le_bytes(anno_offset_buf);
-
-        // Get the raw pixels
-        self.file
-            .seek(SeekFrom::Start(
-                (annotations_loc + offset_to_pixels as i64) as u64,
-            ))
-            .unwrap();
-        let mut pixel_buffer = vec![0u8; pixel_buffer_size as usize];
-        self.file.read_exact(&mut pixel_buffer).unwrap();
+    /// The sensor values of a frame linearised and normalised to black 0.0 / white 1.0.
+    pub fn linear_mosaic(&mut self, frame_no: usize) -> PyResult<Vec<f32>> {
+        let mosaic = self.decoded_frame(frame_no)?;
+        Ok(linearise(
+            &mosaic,
+            &self.compression_type,
+            self.setup.black_level(),
+            self.setup.white_level(),
+        ))
+    }
 
-        // uncompress them into a new vector large enough to hold the decompressed pixels
-        let mut decompressed_pixels =
-            Decompression::decompress(&self.compression_type, &pixel_buffer).unwrap();
-        // apply corrections to the decompressed pixels
+    /// A frame linearised and normalised to black 0.0 / white 1.0, demosaiced to interleaved RGB
+    /// for colour sensors and passed through `correction`. Returns the pixels and the number of
+    /// channels.
+    pub fn linear_frame(
+        &mut self,
+        frame_no: usize,
+        correction: &ColorCorrection,
+    ) -> PyResult<(Vec<f32>, usize)> {
+        let linear = self.linear_mosaic(frame_no)?;
+        let Some(pattern) = self.cfa.pattern() else {
+            return Ok((self.reframe(linear, 1), 1));
+        };
+        let width = self.bitmap_info_header.bi_width as usize;
+        let height = self.bitmap_info_header.bi_height as usize;
+        let mut rgb = demosaic_bilinear(&linear, width, height, pattern);
+        correction.apply(&mut rgb);
+        Ok((self.reframe(rgb, 3), 3))
+    }
 
-        if self.setup.CFA != 0 {
-            let decompressed_pixels =
-                ColorFilterArray::apply_color_array(&self.cfa, &mut decompressed_pixels).unwrap();
-            return Ok(decompressed_pixels.unwrap());
-        } else {
-            ColorFilterArray::apply_color_array(&self.cfa, &mut decompressed_pixels).unwrap();
+    /// Seconds between the trigger and a frame, from the recorded frame times when the file has
+    /// them and otherwise from the image number and frame rate.
+    pub fn time_from_trigger(&self, frame_no: usize) -> f64 {
+        match self.get_frame_time(frame_no) {
+            Some(time) => time.as_secs_f64() - self.cine_file_header.trigger_time.as_secs_f64(),
+            None => self.image_no(frame_no) as f64 / self.setup.frame_rate(),
         }
-        Ok(decompressed_pixels)
     }
 
-    pub fn save_single_frame(&mut self, frame_no: i32, out_path: String) {
-        let width: u32 = self.bitmap_info_header.bi_width as u32;
-        let height: u32 = self.bitmap_info_header.bi_height as u32;
-        let pixels = CineFile::get_frame(self, frame_no);
-        // apply_gamma(self, &mut pixels);
-        let img = ImageBuffer::<Luma<u16>, Vec<u16>>::from_vec(width, height, pixels.unwrap())
-            .expect("pls work?");
+    fn thumbnail_image(&mut self, frame_no: usize, max_size: u32) -> PyResult<RgbImage> {
+        let linear = self.linear_mosaic(frame_no)?;
+        let width = self.bitmap_info_header.bi_width as usize;
+        let height = self.bitmap_info_header.bi_height as usize;
+        let (rgb, width, height, channels) = match self.cfa.pattern() {
+            Some(pattern) => {
+                let mut rgb = demosaic_half(&linear, width, height, pattern);
+                self.display_correction().apply(&mut rgb);
+                (rgb, width / 2, height / 2, 3)
+            }
+            None => (
+                linear.iter().flat_map(|v| [*v; 3]).collect(),
+                width,
+                height,
+                1,
+            ),
+        };
+        let geometry = self.geometry();
+        let rgb = geometry.apply(rgb, width, height, 3);
+        let (width, height) = geometry.output_size(width as u32, height as u32);
+        let image = Rgb32FImage::from_vec(width, height, rgb).unwrap();
+        let (thumb_width, thumb_height) = preview::fit_size(width, height, max_size.max(1));
+        let small = imageops::thumbnail(&image, thumb_width, thumb_height);
+        Ok(RgbImage::from_vec(
+            thumb_width,
+            thumb_height,
+            self.display_render().render(&small, channels),
+        )
+        .unwrap())
+    }
+
+    /// The recorded playback rate in frames per second, 30 when the setup doesn't give one.
+    pub fn playback_rate(&self) -> f64 {
+        match self.setup.fPbRate {
+            rate if rate > 0.0 => rate as f64,
+            _ => 30.0,
+        }
+    }
 
-        img.save(out_path).expect("ohes nose");
+    /// A frame rendered to 8 bits for display, white balanced and in Rec.709 primaries for colour
+    /// sensors. Returns the pixels and the number of channels.
+    pub fn display_frame(&mut self, frame_no: usize) -> PyResult<(Vec<u8>, usize)> {
+        let correction = self.display_correction();
+        let (linear, channels) = self.linear_frame(frame_no, &correction)?;
+        Ok((self.display_render().render(&linear, channels), channels))
     }
 
-    pub fn save_single_colour_frame(
+    /// `get_frame` as a 16-bit image, grey or RGB by the pipeline's channel count.
+    fn frame_image(&mut self, frame_no: i32) -> PyResult<DynamicImage> {
+        let (height, width, channels) = self.frame_shape();
+        let (width, height) = (width as u32, height as u32);
+        let pixels = self.get_frame(frame_no, None)?;
+        let image = match channels {
+            1 => ImageBuffer::<Luma<u16>, _>::from_vec(width, height, pixels)
+                .map(DynamicImage::ImageLuma16),
+            _ => ImageBuffer::<Rgb<u16>, _>::from_vec(width, height, pixels)
+                .map(DynamicImage::ImageRgb16),
+        };
+        image.ok_or_else(|| PyValueError::new_err("Frame doesn't match the image size"))
+    }
+
+    /// `get_frame` as 16-bit values.
+    pub fn get_frame(
         &mut self,
         frame_no: i32,
-        out_path: String,
-    ) -> Result<(), PyErr> {
-        let width: u32 = self.bitmap_info_header.bi_width as u32;
-        let height: u32 = self.bitmap_info_header.bi_height as u32;
-        let pixels = CineFile::get_frame(self, frame_no)?;
-        // apply_gamma(self, &mut pixels);
-        let img = ImageBuffer::<Rgb<u16>, Vec<u16>>::from_raw(width, height, pixels).unwrap();
+        roi: Option<(u32, u32, u32, u32)>,
+    ) -> PyResult<Vec<u16>> {
+        if roi.is_none() && self.cache.is_some() {
+            let pixels = self.cached_frame(frame_no as usize)?;
+            return Ok(pixels.iter().map(|v| quantise(*v)).collect());
+        }
+        let pixels = self.get_frame_f32(frame_no as usize, roi)?;
+        Ok(pixels.into_iter().map(quantise).collect())
+    }
+
+    /// `get_frame` as floats, see there.
+    pub fn get_frame_f32(
+        &mut self,
+        frame_no: usize,
+        roi: Option<(u32, u32, u32, u32)>,
+    ) -> PyResult<Vec<f32>> {
+        match roi {
+            Some(roi) => self.frame_region(frame_no, roi),
+            None if self.cache.is_some() => Ok(self.cached_frame(frame_no)?.to_vec()),
+            None => Ok(self.pipeline().process(self, frame_no)?.0),
+        }
+    }
+
+    /// `get_frame` into `out`, which must be `frame_shape()` long. Reuses the file handle's own
+    /// buffers, see `Pipeline::process_into`.
+    pub fn get_frame_into(&mut self, frame_no: usize, out: &mut [u16]) -> PyResult<()> {
+        self.check_frame_len(out.len())?;
+        let mut frame = mem::take(&mut self.frame);
+        frame.resize(out.len(), 0.0);
+        let result = self.get_frame_into_f32(frame_no, &mut frame).map(|_| {
+            out.iter_mut()
+                .zip(&frame)
+                .for_each(|(o, v)| *o = quantise(*v))
+        });
+        self.frame = frame;
+        result
+    }
+
+    /// `get_frame_f32` into `out`, which must be `frame_shape()` long.
+    pub fn get_frame_into_f32(&mut self, frame_no: usize, out: &mut [f32]) -> PyResult<()> {
+        self.check_frame_len(out.len())?;
+        if self.cache.is_some() {
+            out.copy_from_slice(&self.cached_frame(frame_no)?);
+            return Ok(());
+        }
+        self.pipeline().process_into(self, frame_no, out)
+    }
 
-        img.save(out_path).expect("ohes nose");
+    fn check_frame_len(&self, len: usize) -> PyResult<()> {
+        let (height, width, channels) = self.frame_shape();
+        if len != width * height * channels {
+            return Err(PyValueError::new_err(format!(
+                "Buffer holds {len} values, the frame has {}",
+                width * height * channels
+            )));
+        }
         Ok(())
     }
 
-    pub fn base64_png(&mut self, frame_no: i32) -> Result<String, PyErr> {
-        let width: u32 = self.bitmap_info_header.bi_width as u32;
-        let height: u32 = self.bitmap_info_header.bi_height as u32;
-        let pixels = CineFile::get_frame(self, frame_no)?;
-        let img = ImageBuffer::<Luma<u16>, Vec<u16>>::from_vec(width, height, pixels).unwrap();
+    // Runs `read` over frames `start..end` into one `Reducer` of `len` values, with no more
+    // reducers at a time than fit in `REDUCER_BUDGET`.
+    fn reduce_frames<F>(&self, start: usize, end: usize, len: usize, read: F) -> PyResult<Reducer>
+    where
+        F: Fn(&mut CineFile, usize) -> PyResult<Vec<f32>> + Sync,
+    {
+        let max_runs = REDUCER_BUDGET / (len.max(1) * Reducer::BYTES_PER_VALUE);
+        let runs = self.par_runs(start, end, max_runs, |handle, frames| {
+            let mut reducer = Reducer::new(len);
+            for frame_no in frames {
+                reducer.add(&read(handle, frame_no)?);
+            }
+            Ok(reducer)
+        })?;
+        Ok(runs
+            .into_iter()
+            .reduce(Reducer::merge)
+            .unwrap_or_else(|| Reducer::new(len)))
+    }
 
-        let mut img_png: Vec<u8> = Vec::new();
-        img.write_to(&mut Cursor::new(&mut img_png), ImageFormat::Png)
-            .expect("Failed to convert to png");
-        Ok(general_purpose::STANDARD.encode(img_png))
+    // Splits frames `start..end` into a run per thread, at most `max_runs`, and calls `read` with
+    // each, in parallel through handles of their own. Results are in frame order.
+    fn par_runs<T, F>(&self, start: usize, end: usize, max_runs: usize, read: F) -> PyResult<Vec<T>>
+    where
+        T: Send,
+        F: Fn(&mut CineFile, Range<usize>) -> PyResult<T> + Sync,
+    {
+        self.check_range(start, end)?;
+        let run = (end - start).div_ceil(max_runs.clamp(1, rayon::current_num_threads()));
+        let runs: Vec<usize> = (start..end).step_by(run).collect();
+        runs.into_par_iter()
+            .map(|first| read(&mut self.worker_handle()?, first..(first + run).min(end)))
+            .collect()
     }
 
-    pub fn get_frame_as_png(&mut self, frame_no: i32) -> Result<Vec<u8>, PyErr> {
-        let width: u32 = self.bitmap_info_header.bi_width as u32;
-        let height: u32 = self.bitmap_info_header.bi_height as u32;
-        let pixels = CineFile::get_frame(self, frame_no)?;
-        let img = ImageBuffer::<Luma<u16>, Vec<u16>>::from_vec(width, height, pixels).unwrap();
+    // Another handle on the file, decoding frames the way this one does.
+    fn worker_handle(&self) -> PyResult<CineFile> {
+        let mut handle = CineFile::new(&self.path)?;
+        handle.display_override = self.display_override.clone();
+        handle.geometry_override = self.geometry_override;
+        handle.pipeline_override = self.pipeline_override.clone();
+        handle.defect_map = self.defect_map.clone();
+        Ok(handle)
+    }
 
-        let mut img_png: Vec<u8> = Vec::new();
-        img.write_to(&mut Cursor::new(&mut img_png), ImageFormat::Png)
-            .expect("Failed to convert to png");
-        Ok(img_png)
+    // Cached frames came out of the old settings, drops them and moves the read-ahead thread
+    // over to the new ones.
+    fn reset_cache(&mut self) {
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+        let (pipeline, defect_map) = (self.pipeline(), self.defect_map.clone());
+        if let Some(read_ahead) = &mut self.read_ahead {
+            read_ahead.update(pipeline, defect_map);
+        }
     }
-    // fn save_single_colour_frame(&mut self, frame_no: i32, out_path: String) {
-    //     let width: u32 = self.bitmap_info_header.bi_width as u32;
-    //     let height: u32 = self.bitmap_info_header.bi_height as u32;
-    //     let rgb_pixels = CineFile::get_frame(self, frame_no);
 
-    //     let mut img: ImageBuffer<Rgb<u16>, Vec<u16>> = ImageBuffer::new(width, height);
+    // Linear sensor values of frames `start..end` stacked per pixel.
+    fn stack_linear(
+        &mut self,
+        start: usize,
+        end: usize,
+        method: StackMethod,
+    ) -> PyResult<Vec<f32>> {
+        self.check_range(start, end)?;
+        let pixel_count =
+            self.bitmap_info_header.bi_width as usize * self.bitmap_info_header.bi_height as usize;
+        let mut stack = Stack::new(method, pixel_count);
+        for frame_no in start..end {
+            let sensor = self.get_sensor_frame(frame_no)?;
+            let compression = &self.compression_type;
+            stack.add(
+                sensor
+                    .iter()
+                    .map(|p| compression.to_linear(*p) as f32)
+                    .collect(),
+            );
+        }
+        Ok(stack.finish())
+    }
 
-    //     for y in 0..height {
-    //         for x in 0..width {
-    //             let idx: usize = ((y * width + x) * 3) as usize;
-    //             let r: u16 = rgb_pixels[idx];
-    //             let g: u16 = rgb_pixels[idx + 1];
-    //             let b: u16 = rgb_pixels[idx + 2];
-    //             img.put_pixel(x, y, Rgb([r, g, b]));
-    //         }
-    //     }
+    /// `get_sensor_frame` with the defect map applied.
+    pub fn decoded_frame(&mut self, frame_no: usize) -> PyResult<Vec<u16>> {
+        let mut sensor = Vec::new();
+        self.decode_into(frame_no, &mut sensor)?;
+        Ok(sensor)
+    }
 
-    //     img.save(out_path).unwrap();
-    // }
+    /// `decoded_frame` into `sensor`, resized to the frame, reading through the handle's buffer.
+    pub fn decode_into(&mut self, frame_no: usize, sensor: &mut Vec<u16>) -> PyResult<()> {
+        let mut packed = mem::take(&mut self.packed);
+        let read = self.read_raw_frame(frame_no, &mut packed);
+        self.packed = packed;
+        read?;
+        sensor.resize(self.compression_type.unpacked_len(self.packed.len()), 0);
+        self.compression_type
+            .decompress_into(&self.packed, sensor)
+            .map_err(|_| PyValueError::new_err("Failed to unpack frame"))?;
+        // Drop the padding of the last packing group.
+        sensor.truncate(
+            self.bitmap_info_header.bi_width as usize * self.bitmap_info_header.bi_height as usize,
+        );
+        if let Some(map) = &self.defect_map {
+            map.correct(sensor, self.cfa.pattern().is_some());
+        }
+        Ok(())
+    }
+
+    // Lends the sensor and mosaic buffers out, hand them back with `restore_buffers`.
+    pub(crate) fn take_buffers(&mut self) -> (Vec<u16>, Vec<f32>) {
+        (mem::take(&mut self.sensor), mem::take(&mut self.mosaic))
+    }
+
+    pub(crate) fn restore_buffers(&mut self, sensor: Vec<u16>, mosaic: Vec<f32>) {
+        (self.sensor, self.mosaic) = (sensor, mosaic);
+    }
+
+    // The frame through `pipeline()` from the cache, processed and kept there when it's missing,
+    // queueing read-ahead past the frame. Hits share the cached pixels.
+    fn cached_frame(&mut self, frame_no: usize) -> PyResult<Arc<[f32]>> {
+        self.collect_read_ahead();
+        let cached = self
+            .cache
+            .as_mut()
+            .and_then(|cache| cache.get(frame_no).map(|(pixels, _)| pixels.clone()));
+        let pixels = match cached {
+            Some(pixels) => pixels,
+            None => {
+                let (pixels, channels) = self.pipeline().process(self, frame_no)?;
+                let pixels: Arc<[f32]> = pixels.into();
+                if let Some(cache) = self.cache.as_mut() {
+                    cache.insert(frame_no, pixels.clone(), channels);
+                }
+                pixels
+            }
+        };
+
+        let backwards = self.last_frame.is_some_and(|last| last > frame_no);
+        self.last_frame = Some(frame_no);
+        if let (Some(read_ahead), Some(cache)) = (&self.read_ahead, &self.cache) {
+            let image_count = self.p_images.len();
+            let ahead = (1..=read_ahead.count)
+                .filter_map(|step| match backwards {
+                    true => frame_no.checked_sub(step),
+                    false => Some(frame_no + step).filter(|f| *f < image_count),
+                })
+                .filter(|f| !cache.contains(*f))
+                .collect();
+            read_ahead.request(ahead);
+        }
+        Ok(pixels)
+    }
+
+    // Moves frames the read-ahead thread has finished into the cache.
+    fn collect_read_ahead(&mut self) {
+        if let (Some(read_ahead), Some(cache)) = (&self.read_ahead, &mut self.cache) {
+            for (frame_no, (pixels, channels)) in read_ahead.finished() {
+                cache.insert(frame_no, pixels, channels);
+            }
+        }
+    }
+
+    // `get_frame_f32` of a region, see there.
+    fn frame_region(
+        &mut self,
+        frame_no: usize,
+        (x, y, w, h): (u32, u32, u32, u32),
+    ) -> PyResult<Vec<f32>> {
+        self.check_roi((x, y, w, h))?;
+        let roi = (x as usize, y as usize, w as usize, h as usize);
+        Ok(self.pipeline().process_region(self, frame_no, roi)?.0)
+    }
+
+    fn check_roi(&self, (x, y, w, h): (u32, u32, u32, u32)) -> PyResult<()> {
+        let width = self.bitmap_info_header.bi_width as u32;
+        let height = self.bitmap_info_header.bi_height as u32;
+        if w == 0 || h == 0 || x + w > width || y + h > height {
+            return Err(PyValueError::new_err(
+                "ROI must be a non-empty area inside the frame",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Unpacked sensor values of the (x, y, width, height) region of a frame. Only the rows it
+    /// covers are read and only the packing groups holding its columns are unpacked. `None` when
+    /// rows don't start on a byte boundary, which needs the whole frame unpacked instead.
+    pub fn read_sensor_region(
+        &mut self,
+        frame_no: usize,
+        (x, y, w, h): (usize, usize, usize, usize),
+    ) -> PyResult<Option<Vec<u16>>> {
+        let row_bits =
+            self.bitmap_info_header.bi_width as usize * self.compression_type.bit_depth() as usize;
+        if !row_bits.is_multiple_of(8) {
+            return Ok(None);
+        }
+        let row_bytes = row_bits / 8;
+        let (group_pixels, group_bytes) = self.compression_type.group_size();
+        let first_group = x / group_pixels;
+        let span_bytes = ((x + w).div_ceil(group_pixels) - first_group) * group_bytes;
+
+        let image_start = self.image_data_offset(frame_no)?;
+        self.file.seek(SeekFrom::Start(
+            image_start + (y * row_bytes + first_group * group_bytes) as u64,
+        ))?;
+        let mut packed = vec![0u8; (h - 1) * row_bytes + span_bytes];
+        self.file.read_exact(&mut packed)?;
+
+        let skip = x - first_group * group_pixels;
+        let mut region = Vec::with_capacity(w * h);
+        let mut pixels = vec![0u16; self.compression_type.unpacked_len(span_bytes)];
+        for row in 0..h {
+            let span = &packed[row * row_bytes..row * row_bytes + span_bytes];
+            self.compression_type
+                .decompress_into(span, &mut pixels)
+                .map_err(|_| PyValueError::new_err("Failed to unpack frame"))?;
+            region.extend_from_slice(&pixels[skip..skip + w]);
+        }
+        Ok(Some(region))
+    }
+
+    // Reads a frame's packed pixels into `packed`, resized to the stored image size.
+    fn read_raw_frame(&mut self, frame_no: usize, packed: &mut Vec<u8>) -> io::Result<()> {
+        let image_start = self.image_data_offset(frame_no)?;
+        self.file.seek(SeekFrom::Start(image_start))?;
+        packed.resize(self.bitmap_info_header.bi_size_image as usize, 0);
+        self.file.read_exact(packed)
+    }
+
+    // File offset of a frame's pixels, past its annotation.
+    fn image_data_offset(&mut self, frame_no: usize) -> io::Result<u64> {
+        let annotations_loc = *self.p_images.get(frame_no).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Frame number out of range")
+        })?;
+        self.file.seek(SeekFrom::Start(annotations_loc as u64))?;
+        let mut anno_size_buf = [0u8; 4];
+        self.file.read_exact(&mut anno_size_buf)?;
+        Ok(annotations_loc as u64 + u32::from_le_bytes(anno_size_buf) as u64)
+    }
+
+    /// Applies the geometry (crop, orientation, resample) to a full frame of `channels` samples
+    /// per pixel.
+    fn reframe<T: Sample>(&self, pixels: Vec<T>, channels: usize) -> Vec<T> {
+        self.geometry().apply(
+            pixels,
+            self.bitmap_info_header.bi_width as usize,
+            self.bitmap_info_header.bi_height as usize,
+            channels,
+        )
+    }
+
+    /// The colour stage for display, as PCC shows colour footage.
+    pub fn display_correction(&self) -> ColorCorrection {
+        ColorCorrection::from_setup(&self.setup, ColorSpace::LinearSrgb, true)
+    }
+
+    /// The display processing in use, the setup's unless overridden from Python.
+    pub fn display_render(&self) -> DisplayRender {
+        match &self.display_override {
+            Some(render) => render.clone(),
+            None => DisplayRender::from_setup(&self.setup, &self.compression_type),
+        }
+    }
+
+    /// Reads an image as stored; the annotation data (without its size fields) and the packed pixels.
+    pub fn read_annotated_image(&mut self, frame_no: usize) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let annotations_loc = *self.p_images.get(frame_no).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Frame number out of range")
+        })?;
+        self.file.seek(SeekFrom::Start(annotations_loc as u64))?;
+        let mut anno_size_buf = [0u8; 4];
+        self.file.read_exact(&mut anno_size_buf)?;
+        let annotation_size = u32::from_le_bytes(anno_size_buf) as usize;
+
+        // AnnotationSize includes itself and the trailing ImageSize field.
+        let mut annotation = vec![0u8; annotation_size.saturating_sub(8)];
+        self.file.read_exact(&mut annotation)?;
+        self.file.seek(SeekFrom::Start(
+            annotations_loc as u64 + annotation_size as u64,
+        ))?;
+        let mut pixels = vec![0u8; self.bitmap_info_header.bi_size_image as usize];
+        self.file.read_exact(&mut pixels)?;
+        Ok((annotation, pixels))
+    }
+}
+
+fn encode_png(image: impl Into<DynamicImage>) -> PyResult<Vec<u8>> {
+    let mut png = Vec::new();
+    image
+        .into()
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| PyIOError::new_err(e.to_string()))?;
+    Ok(png)
+}
+
+// Runs `f` on the contents of a writable, C-contiguous buffer.
+fn with_writable<T: Element, R>(
+    buffer: &PyBuffer<T>,
+    f: impl FnOnce(&mut [T]) -> PyResult<R>,
+) -> PyResult<R> {
+    if buffer.readonly() || !buffer.is_c_contiguous() {
+        return Err(PyValueError::new_err(
+            "Buffer must be writable and C-contiguous",
+        ));
+    }
+    // SAFETY: the buffer is writable, contiguous and aligned for `T` (checked by `PyBuffer`) and
+    // stays exported while `buffer` lives. This assumes nothing else reads or writes its memory
+    // while `f` runs. We hold the GIL and don't call back into Python, but an overlapping view
+    // written by a thread that released the GIL isn't ruled out, and isn't checked for.
+    let out =
+        unsafe { std::slice::from_raw_parts_mut(buffer.buf_ptr() as *mut T, buffer.item_count()) };
+    f(out)
 }
 
 fn read_structs<T: Copy, R: Read>(mut reader: R) -> io::Result<T> {
@@ -201,8 +1464,70 @@ fn read_structs<T: Copy, R: Read>(mut reader: R) -> io::Result<T> {
     Ok(result)
 }
 
+/// Reads the setup block honouring `Setup.Length`. In older files the bytes past the stored length
+/// belong to the next block, so the remainder of the structure is left zeroed instead. Returns the
+/// setup, the bytes read and whether `Length` was used.
+fn read_setup<R: Read>(
+    mut reader: R,
+    space: usize,
+    fallback: cine::SetupRevision,
+) -> io::Result<(cine::PackedSetup, usize, bool)> {
+    let full_size = mem::size_of::<cine::PackedSetup>();
+    let length_end = mem::offset_of!(cine::PackedSetup, Length) + mem::size_of::<u16>();
+    let mut buffer = vec![0u8; full_size];
+    reader.read_exact(&mut buffer[..length_end])?;
+
+    // A Length shorter than the fields up to itself or running past the `space` before the next
+    // block is junk, the layout then comes from `fallback`.
+    let stored_length = u16::from_le_bytes([buffer[length_end - 2], buffer[length_end - 1]]);
+    let trusted = (length_end..=space).contains(&(stored_length as usize));
+    let setup_length = match trusted {
+        true => (stored_length as usize).min(full_size),
+        false => fallback.length().min(space).max(length_end),
+    };
+    reader.read_exact(&mut buffer[length_end..setup_length])?;
+
+    Ok((
+        cine::PackedSetup::from_bytes(&buffer),
+        setup_length,
+        trusted,
+    ))
+}
+
+/// Reads the tagged information blocks stored between `start` and `end`.
+fn read_tagged_blocks<R: Read + Seek>(
+    mut reader: R,
+    start: u64,
+    end: u64,
+) -> io::Result<Vec<cine::TaggedBlock>> {
+    let mut blocks = Vec::new();
+    let mut pos = start;
+    while pos + cine::TAGGED_BLOCK_HEADER_SIZE as u64 <= end {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; cine::TAGGED_BLOCK_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        let block_size = u32::from_le_bytes(header[0..4].try_into().unwrap()) as u64;
+        if block_size < cine::TAGGED_BLOCK_HEADER_SIZE as u64 || pos + block_size > end {
+            break;
+        }
+        let mut data = vec![0u8; block_size as usize - cine::TAGGED_BLOCK_HEADER_SIZE];
+        reader.read_exact(&mut data)?;
+        blocks.push(cine::TaggedBlock {
+            block_type: u16::from_le_bytes([header[4], header[5]]),
+            reserved: u16::from_le_bytes([header[6], header[7]]),
+            data,
+        });
+        pos += block_size;
+    }
+    Ok(blocks)
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::defects::DefectKind;
+    use crate::geometry;
+    use crate::testing::{TempFile, write_cine};
 
     #[test]
 
@@ -211,4 +1536,437 @@ mod tests {
         let b = 5;
         assert_eq!(a * b, 10);
     }
+
+    #[test]
+    fn test_trim() {
+        let (src, out) = (
+            TempFile::new("cine_py_trim_src.cine"),
+            TempFile::new("cine_py_trim_out.cine"),
+        );
+        let mut writer =
+            CineWriter::new(src.path(), cine::Setup::new(8, 2, 10, 100), 5, 10, 0, None).unwrap();
+        let range_data: Vec<u8> = (0..5u32).flat_map(|i| i.to_le_bytes()).collect();
+        writer
+            .add_tagged_block(cine::TAG_RANGE_DATA, range_data)
+            .unwrap();
+        for f in 0..5u16 {
+            writer
+                .write_frame(vec![f; 16], None, Some(f as u32))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut source = src.open();
+        assert!(source.trim(3, 6, out.path()).is_err());
+        source.trim(1, 4, out.path()).unwrap();
+
+        let mut trimmed = out.open();
+        assert_eq!(trimmed.cine_file_header.image_count, 3);
+        assert_eq!(trimmed.cine_file_header.total_image_count, 3);
+        assert_eq!(trimmed.cine_file_header.first_image_no, 1);
+        assert_eq!(trimmed.get_sensor_frame(0).unwrap(), vec![1; 16]);
+        assert_eq!(trimmed.get_frame_exposure(2), Some(3));
+        assert_eq!(trimmed.get_frame_exposure(3), None);
+        assert!(trimmed.get_frame_time(3).is_none());
+        assert_eq!(
+            trimmed.get_frame_time(0).unwrap().as_secs_f64(),
+            source.get_frame_time(1).unwrap().as_secs_f64()
+        );
+        let range = trimmed.tagged_block(cine::TAG_RANGE_DATA).unwrap();
+        assert_eq!(range.exposures(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_frame_region() {
+        let file = write_cine(
+            "cine_py_roi.cine",
+            cine::Setup::new(8, 4, 10, 100),
+            &[(0..32).collect()],
+        );
+        let mut cine_file = file.open();
+        let full = cine_file.get_frame(0, None).unwrap();
+        let roi = cine_file.get_frame(0, Some((2, 1, 3, 2))).unwrap();
+        assert_eq!(roi, [10, 11, 12, 18, 19, 20].map(|i| full[i]));
+        assert!(cine_file.get_frame(0, Some((6, 0, 3, 1))).is_err());
+
+        // Regions are oriented like whole frames.
+        cine_file
+            .set_geometry(Some(Geometry {
+                flip_h: true,
+                ..Default::default()
+            }))
+            .unwrap();
+        let roi = cine_file.get_frame(0, Some((2, 1, 3, 2))).unwrap();
+        assert_eq!(roi, [12, 11, 10, 20, 19, 18].map(|i| full[i]));
+
+        // A crop outside the frame is refused and the geometry kept.
+        let outside = Geometry {
+            crop: Some((4, 0, 6, 2)),
+            ..Default::default()
+        };
+        assert!(cine_file.set_geometry(Some(outside)).is_err());
+        assert!(cine_file.geometry().flip_h);
+    }
+
+    #[test]
+    fn test_colour_frame_region() {
+        let mut setup = cine::Setup::new(16, 12, 12, 100);
+        setup.CFA = 3;
+        let frame: Vec<u16> = (0..16 * 12).map(|v| (v * 937 % 4096) as u16).collect();
+        let file = write_cine("cine_py_colour_roi.cine", setup, &[frame]);
+        let mut cine_file = file.open();
+        let full = cine_file.get_frame_f32(0, None).unwrap();
+        // Odd origins, both frame corners and the whole frame.
+        for roi in [(5, 3, 4, 5), (0, 0, 3, 3), (13, 9, 3, 3), (0, 0, 16, 12)] {
+            let (x, y, w, h) = roi;
+            let region = cine_file.get_frame_f32(0, Some(roi)).unwrap();
+            let expected = geometry::crop(
+                &full,
+                16,
+                3,
+                (x as usize, y as usize, w as usize, h as usize),
+            );
+            assert_eq!(region, expected, "{roi:?}");
+        }
+    }
+
+    #[test]
+    fn test_frame_stages() {
+        let pixels: Vec<u16> = (0..32).map(|v| v * 31).collect();
+        let file = write_cine(
+            "cine_py_stages.cine",
+            cine::Setup::new(8, 4, 10, 100),
+            std::slice::from_ref(&pixels),
+        );
+        let mut cine_file = file.open();
+        let raw = cine_file.get_raw_frame(0).unwrap();
+        assert_eq!(raw, Decompression::Packed10Bit.compress(&pixels).unwrap());
+        assert_eq!(cine_file.get_sensor_frame(0).unwrap(), pixels);
+        assert!(cine_file.get_raw_frame(1).is_err());
+
+        // Floats are linearised and normalised to the black and white levels.
+        let linear = |p: u16| Decompression::Packed10Bit.to_linear(p) as f32;
+        let (black, white) = (linear(0), linear(1023));
+        let expected: Vec<f32> = pixels
+            .iter()
+            .map(|p| (linear(*p) - black) / (white - black))
+            .collect();
+        assert_eq!(cine_file.get_frame_f32(0, None).unwrap(), expected);
+        let processed: Vec<u16> = expected.iter().map(|v| quantise(*v)).collect();
+        assert_eq!(cine_file.get_frame(0, None).unwrap(), processed);
+        let mut out = vec![0.0f32; 32];
+        cine_file.get_frame_into_f32(0, &mut out).unwrap();
+        assert_eq!(out, expected);
+        assert_eq!(
+            cine_file.get_frame_f32(0, Some((1, 1, 2, 1))).unwrap(),
+            expected[9..11]
+        );
+    }
+
+    #[test]
+    fn test_linear_by_default() {
+        let mut setup = cine::Setup::new(4, 2, 12, 100);
+        setup.fGamma = 2.2;
+        let file = write_cine("cine_py_linear.cine", setup, &[vec![2048; 8]]);
+        let mut cine_file = file.open();
+        // Mid-grey stays mid-grey, the setup's gamma only applies when asked for.
+        let grey = 2048.0 / 4095.0;
+        assert_eq!(cine_file.get_frame_f32(0, None).unwrap(), vec![grey; 8]);
+        assert_eq!(
+            cine_file.get_frame(0, None).unwrap(),
+            vec![quantise(grey); 8]
+        );
+
+        let rendered = cine_file.rendered_pipeline();
+        assert_eq!(rendered.gamma, Some([2.2; 3]));
+        Python::with_gil(|py| cine_file.set_pipeline(py, Some(rendered))).unwrap();
+        let frame = cine_file.get_frame_f32(0, None).unwrap();
+        assert!((frame[0] - grey.powf(1.0 / 2.2)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_frame_images() {
+        for cfa in [0, 3] {
+            let mut setup = cine::Setup::new(4, 2, 12, 100);
+            setup.CFA = cfa;
+            let file = write_cine("cine_py_frame_images.cine", setup, &[vec![1000; 8]]);
+            let mut cine_file = file.open();
+            for demosaic in [true, false] {
+                let pipeline = Pipeline {
+                    demosaic,
+                    ..cine_file.pipeline()
+                };
+                Python::with_gil(|py| cine_file.set_pipeline(py, Some(pipeline))).unwrap();
+                let png = cine_file.get_frame_as_png(0, true).unwrap();
+                let image = image::load_from_memory(&png).unwrap();
+                let expected = match cine_file.frame_shape().2 {
+                    1 => image::ColorType::L16,
+                    _ => image::ColorType::Rgb16,
+                };
+                assert_eq!(
+                    (image.color(), image.width(), image.height()),
+                    (expected, 4, 2)
+                );
+
+                let pattern = std::env::temp_dir().join("cine_py_frame_images_{frame}.tiff");
+                let paths = cine_file
+                    .export_tiff_sequence(0, 1, pattern.to_str().unwrap())
+                    .unwrap();
+                let tiff = image::open(&paths[0]).unwrap();
+                std::fs::remove_file(&paths[0]).unwrap();
+                assert_eq!(tiff.color(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dtypes_share_a_chain() {
+        for cfa in [0, 3] {
+            let mut setup = cine::Setup::new(8, 4, 12, 100);
+            setup.CFA = cfa;
+            setup.fGamma = 2.2;
+            setup.fGainR = 1.5;
+            let frame: Vec<u16> = (0..32).map(|v| v * 120).collect();
+            let file = write_cine("cine_py_dtypes.cine", setup, &[frame]);
+            let mut cine_file = file.open();
+            for roi in [None, Some((2, 1, 4, 2))] {
+                let float = cine_file.get_frame_f32(0, roi).unwrap();
+                let expected: Vec<u16> = float.iter().map(|v| quantise(*v)).collect();
+                assert_eq!(cine_file.get_frame(0, roi).unwrap(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_frame_into() {
+        for cfa in [0, 3] {
+            let mut setup = cine::Setup::new(8, 4, 10, 100);
+            setup.CFA = cfa;
+            let frames: Vec<Vec<u16>> = (0..2).map(|f| (0..32).map(|v| v + f).collect()).collect();
+            let file = write_cine("cine_py_frame_into.cine", setup, &frames);
+            let mut cine_file = file.open();
+            let (height, width, channels) = cine_file.frame_shape();
+            assert_eq!(channels, if cfa == 0 { 1 } else { 3 });
+            let mut out = vec![0u16; width * height * channels];
+            for f in 0..2 {
+                cine_file.get_frame_into(f, &mut out).unwrap();
+                assert_eq!(out, cine_file.get_frame(f as i32, None).unwrap());
+            }
+            assert!(cine_file.get_frame_into(0, &mut out[1..]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_frame_stats() {
+        let frames: Vec<Vec<u16>> = (0..9)
+            .map(|f| (0..32).map(|v| v * 100 + f * 10).collect())
+            .collect();
+        let mut setup = cine::Setup::new(8, 4, 12, 100);
+        setup.fGamma = 2.2;
+        let file = write_cine("cine_py_frame_stats.cine", setup, &frames);
+        let mut cine_file = file.open();
+        let frames: Vec<Vec<f32>> = (2..9)
+            .map(|f| cine_file.get_frame_f32(f, None).unwrap())
+            .collect();
+        // Statistics stay linear with a gamma in the pipeline.
+        let rendered = cine_file.rendered_pipeline();
+        Python::with_gil(|py| cine_file.set_pipeline(py, Some(rendered))).unwrap();
+        let stats = Python::with_gil(|py| cine_file.frame_stats(py, 2, 9)).unwrap();
+        assert_eq!(
+            (stats.width, stats.height, stats.channels, stats.count),
+            (8, 4, 1, 7)
+        );
+        assert_eq!(stats.min, frames[0]);
+        assert_eq!(stats.max, frames[6]);
+        for (i, mean) in stats.mean.iter().enumerate() {
+            assert!((mean - frames[3][i]).abs() < 1e-6);
+        }
+        assert!(Python::with_gil(|py| cine_file.frame_stats(py, 2, 10)).is_err());
+
+        let runs = cine_file.par_runs(2, 9, 1, |_, frames| Ok(frames)).unwrap();
+        assert_eq!(runs, vec![2..9]);
+    }
+
+    #[test]
+    fn test_frame_summary() {
+        let frames: Vec<Vec<u16>> = (0..5)
+            .map(|f| {
+                let mut pixels = vec![2000u16; 32];
+                pixels[..f].fill(4095);
+                pixels
+            })
+            .collect();
+        let file = write_cine(
+            "cine_py_frame_summary.cine",
+            cine::Setup::new(8, 4, 12, 100),
+            &frames,
+        );
+        let cine_file = file.open();
+        let summary = Python::with_gil(|py| cine_file.frame_summary(py, 4, 1, None)).unwrap();
+        assert_eq!((summary.bins, summary.channels), (4, 1));
+        assert_eq!(summary.frames, vec![1, 2, 3, 4]);
+        let times: Vec<f64> = (1..5).map(|f| cine_file.time_from_trigger(f)).collect();
+        assert_eq!(summary.times, times);
+        assert_eq!(summary.saturated, vec![1, 2, 3, 4]);
+        assert_eq!(summary.max, vec![1.0; 4]);
+        assert_eq!(&summary.histograms[12..], &[0, 28, 0, 4]);
+        assert!(Python::with_gil(|py| cine_file.frame_summary(py, 0, 0, None)).is_err());
+    }
+
+    #[test]
+    fn test_defect_map() {
+        let frames: Vec<Vec<u16>> = (0..4)
+            .map(|f| {
+                let mut pixels = vec![100 + f * 10; 32];
+                pixels[11] = 4000;
+                pixels
+            })
+            .collect();
+        let file = write_cine(
+            "cine_py_defect_map.cine",
+            cine::Setup::new(8, 4, 12, 100),
+            &frames,
+        );
+        let mut cine_file = file.open();
+        let detect = |cine_file: &CineFile| {
+            Python::with_gil(|py| cine_file.detect_defects(py, 0, 4, 0.1, 0.3)).unwrap()
+        };
+        let map = detect(&cine_file);
+        assert_eq!(map.defects, vec![(3, 1, DefectKind::Hot)]);
+        cine_file.set_cache(1 << 20, 0).unwrap();
+        assert_ne!(
+            cine_file.get_frame(1, None).unwrap()[11],
+            cine_file.get_frame(1, None).unwrap()[10]
+        );
+
+        cine_file.set_defect_map(Some(map)).unwrap();
+        let frame = cine_file.get_frame(1, None).unwrap();
+        assert_eq!(frame[11], frame[10]);
+        assert_eq!(cine_file.get_sensor_frame(1).unwrap()[11], 4000);
+        // Detection looks at the stored values, not the corrected ones.
+        assert_eq!(detect(&cine_file).defects.len(), 1);
+        assert!(
+            cine_file
+                .set_defect_map(Some(DefectMap::new(4, 4, vec![]).unwrap()))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_roi_spans() {
+        for bits in [10, 12] {
+            let setup = cine::Setup::new(16, 6, bits, 100);
+            let file = write_cine("cine_py_roi_spans.cine", setup, &[(0..96).collect()]);
+            let mut cine_file = file.open();
+            let full = cine_file.get_frame(0, None).unwrap();
+            let roi = cine_file.get_roi(0, 5, 2, 6, 3).unwrap();
+            let expected = geometry::crop(&full, 16, 1, (5, 2, 6, 3));
+            assert_eq!(roi, expected);
+        }
+    }
+
+    #[test]
+    fn test_cache_read_ahead() {
+        let frames: Vec<Vec<u16>> = (0..10)
+            .map(|f| (0..16).map(|i| f * 16 + i).collect())
+            .collect();
+        let file = write_cine(
+            "cine_py_cache.cine",
+            cine::Setup::new(8, 2, 10, 100),
+            &frames,
+        );
+        let mut cine_file = file.open();
+        cine_file.set_cache(1 << 20, 3).unwrap();
+        cine_file.get_frame(5, None).unwrap();
+        // Going backwards from 5 reads 3, 2 and 1 ahead. Frames read ahead of 5 may land too.
+        cine_file.get_frame(4, None).unwrap();
+        let cached = |cine_file: &mut CineFile| {
+            cine_file.cache_info();
+            let cache = cine_file.cache.as_ref().unwrap();
+            (1..=5).all(|f| cache.contains(f))
+        };
+        let wait_for_read_ahead = |cine_file: &mut CineFile| {
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+            while !cached(cine_file) && std::time::Instant::now() < deadline {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            assert!(cached(cine_file));
+        };
+        wait_for_read_ahead(&mut cine_file);
+        assert_eq!(
+            cine_file.get_frame(2, None).unwrap(),
+            file.open().get_frame(2, None).unwrap()
+        );
+        // Cached frames went through the old geometry, the read-ahead thread moves to the new one.
+        let flipped = Geometry {
+            flip_v: true,
+            ..Default::default()
+        };
+        cine_file.set_geometry(Some(flipped)).unwrap();
+        assert_eq!(cine_file.cache_info(), (0, 0));
+        cine_file.get_frame(5, None).unwrap();
+        cine_file.get_frame(4, None).unwrap();
+        wait_for_read_ahead(&mut cine_file);
+        let mut uncached = file.open();
+        uncached.set_geometry(Some(flipped)).unwrap();
+        assert_eq!(
+            cine_file.get_frame(2, None).unwrap(),
+            uncached.get_frame(2, None).unwrap()
+        );
+
+        cine_file.set_cache(0, 0).unwrap();
+        assert_eq!(cine_file.cache_info(), (0, 0));
+    }
+
+    #[test]
+    fn test_short_setup_is_zero_filled() {
+        // A setup that stops after WhiteLevel, followed by bytes from the next block.
+        let (offset, size) = cine::Setup::packed_field("WhiteLevel").unwrap();
+        let length = offset + size;
+        let mut bytes = vec![0u8; length];
+        let length_at = mem::offset_of!(cine::PackedSetup, Length);
+        bytes[length_at..length_at + 2].copy_from_slice(&(length as u16).to_le_bytes());
+        bytes[offset..length].copy_from_slice(&1014i32.to_le_bytes());
+        bytes.extend(vec![0xFFu8; 64]);
+
+        let full_size = mem::size_of::<cine::PackedSetup>();
+        let fallback = cine::SetupRevision::Current;
+        let (packed, setup_length, trusted) =
+            read_setup(Cursor::new(bytes), full_size, fallback).unwrap();
+        let setup = cine::Setup::from(packed);
+        assert!(trusted);
+        assert_eq!(setup_length, length);
+        assert_eq!(setup.WhiteLevel, 1014);
+        assert_eq!(setup.fGain, 0.0);
+        assert_eq!(
+            cine::SetupRevision::from_length(setup_length),
+            cine::SetupRevision::ImageProcessing
+        );
+    }
+
+    #[test]
+    fn test_junk_setup_length() {
+        let full_size = mem::size_of::<cine::PackedSetup>();
+        let length_at = mem::offset_of!(cine::PackedSetup, Length);
+        let (offset, size) = cine::Setup::packed_field("WhiteLevel").unwrap();
+        for (stored, version, revision) in [
+            (0u16, 1, cine::SetupRevision::Current),
+            (u16::MAX, 1, cine::SetupRevision::Current),
+            (0, 0, cine::SetupRevision::Legacy),
+        ] {
+            let mut bytes = vec![0u8; full_size];
+            bytes[length_at..length_at + 2].copy_from_slice(&stored.to_le_bytes());
+            bytes[offset..offset + size].copy_from_slice(&1014i32.to_le_bytes());
+
+            let fallback = cine::SetupRevision::from_version(version);
+            let (packed, setup_length, trusted) =
+                read_setup(Cursor::new(bytes), full_size, fallback).unwrap();
+            assert!(!trusted);
+            assert_eq!(setup_length, revision.length());
+            assert_eq!(cine::SetupRevision::from_length(setup_length), revision);
+            // Legacy setups stop before WhiteLevel.
+            let white = cine::Setup::from(packed).WhiteLevel;
+            assert_eq!(white, if version == 0 { 0 } else { 1014 });
+        }
+    }
 }
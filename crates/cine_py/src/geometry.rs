@@ -0,0 +1,436 @@
+// Framing of processed frames; the crop, flips, rotation and output size stored in the setup for
+// cameras mounted on their side or upside down and for the operator's chosen framing.
+
+use crate::cine;
+use crate::conversions::flip_vertical;
+use image::imageops::{self, FilterType};
+use image::{ImageBuffer, Luma, Pixel, Primitive, Rgb};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+/// Filter used when resampling to a new output size.
+#[pyclass(module = "cinepy", eq, eq_int)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResizeFilter {
+    Nearest,
+    #[default]
+    Triangle,
+    CatmullRom,
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// A crop, flips, a rotation and a resample, in that order, applied to processed frames before
+/// they're returned or encoded. Raw outputs (`get_sensor_frame`, DNG, `trim`) keep the stored
+/// frame. Get the one in use with `CineFile.geometry()` and override it with
+/// `CineFile.set_geometry()`.
+#[pyclass(module = "cinepy", get_all)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Geometry {
+    pub flip_h: bool,
+    pub flip_v: bool,
+    /// Degrees counter clockwise, a multiple of 90.
+    pub rotate: i32,
+    /// (x, y, width, height) in stored frame coordinates, inside the frame.
+    pub crop: Option<(u32, u32, u32, u32)>,
+    /// Final (width, height).
+    pub resample: Option<(u32, u32)>,
+    pub filter: ResizeFilter,
+}
+
+#[pymethods]
+impl Geometry {
+    #[new]
+    #[pyo3(signature = (flip_h=false, flip_v=false, rotate=0, crop=None, resample=None, filter=ResizeFilter::Triangle))]
+    pub fn new(
+        flip_h: bool,
+        flip_v: bool,
+        rotate: i32,
+        crop: Option<(u32, u32, u32, u32)>,
+        resample: Option<(u32, u32)>,
+        filter: ResizeFilter,
+    ) -> PyResult<Self> {
+        let geometry = Self {
+            flip_h,
+            flip_v,
+            rotate,
+            crop,
+            resample,
+            filter,
+        };
+        geometry.validate()?;
+        Ok(geometry)
+    }
+
+    #[setter]
+    fn set_flip_h(&mut self, flip_h: bool) {
+        self.flip_h = flip_h;
+    }
+
+    #[setter]
+    fn set_flip_v(&mut self, flip_v: bool) {
+        self.flip_v = flip_v;
+    }
+
+    #[setter]
+    fn set_rotate(&mut self, rotate: i32) -> PyResult<()> {
+        Self { rotate, ..*self }.validate()?;
+        self.rotate = rotate;
+        Ok(())
+    }
+
+    #[setter]
+    fn set_crop(&mut self, crop: Option<(u32, u32, u32, u32)>) -> PyResult<()> {
+        Self { crop, ..*self }.validate()?;
+        self.crop = crop;
+        Ok(())
+    }
+
+    #[setter]
+    fn set_resample(&mut self, resample: Option<(u32, u32)>) -> PyResult<()> {
+        Self { resample, ..*self }.validate()?;
+        self.resample = resample;
+        Ok(())
+    }
+
+    #[setter]
+    fn set_filter(&mut self, filter: ResizeFilter) {
+        self.filter = filter;
+    }
+
+    /// Size of a `width` x `height` frame after the crop, rotation and resample.
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if let Some(size) = self.resample {
+            return size;
+        }
+        let (_, _, width, height) = self.crop_rect(width, height);
+        self.rotated_size(width, height)
+    }
+}
+
+impl Geometry {
+    /// From `bFlipH`, `bFlipV` and `Rotate`; rotations that aren't a multiple of 90 are ignored.
+    pub fn from_setup(setup: &cine::Setup) -> Self {
+        Self {
+            flip_h: setup.bFlipH != 0,
+            flip_v: setup.bFlipV != 0,
+            rotate: if setup.Rotate % 90 == 0 {
+                setup.Rotate
+            } else {
+                0
+            },
+            ..Default::default()
+        }
+    }
+
+    /// `from_setup` plus `CropRect` and the resample size when `EnableCrop`/`EnableResample` are
+    /// set. `CropRect` is clipped to the setup's `ImWidth` x `ImHeight`.
+    pub fn from_setup_framing(setup: &cine::Setup, filter: ResizeFilter) -> Self {
+        let rect = setup.CropRect;
+        let (left, top) = (rect.left.max(0), rect.top.max(0));
+        let right = rect.right.min(setup.ImWidth as i32);
+        let bottom = rect.bottom.min(setup.ImHeight as i32);
+        let crop = (setup.EnableCrop != 0 && right > left && bottom > top).then_some((
+            left as u32,
+            top as u32,
+            (right - left) as u32,
+            (bottom - top) as u32,
+        ));
+        let resample =
+            (setup.EnableResample != 0 && setup.ResampleWidth > 0 && setup.ResampleHeight > 0)
+                .then_some((setup.ResampleWidth, setup.ResampleHeight));
+        Self {
+            crop,
+            resample,
+            filter,
+            ..Self::from_setup(setup)
+        }
+    }
+
+    fn quarter_turns(&self) -> i32 {
+        (self.rotate / 90).rem_euclid(4)
+    }
+
+    fn rotated_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.quarter_turns() {
+            1 | 3 => (height, width),
+            _ => (width, height),
+        }
+    }
+
+    fn validate(&self) -> PyResult<()> {
+        if self.rotate % 90 != 0 {
+            return Err(PyValueError::new_err(
+                "rotate must be a multiple of 90 degrees",
+            ));
+        }
+        if self.crop.is_some_and(|(_, _, w, h)| w == 0 || h == 0)
+            || self.resample.is_some_and(|(w, h)| w == 0 || h == 0)
+        {
+            return Err(PyValueError::new_err(
+                "crop and resample sizes must be positive",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the crop lies inside a `width` x `height` frame.
+    pub fn check_frame(&self, width: u32, height: u32) -> PyResult<()> {
+        match self.crop {
+            Some((x, y, w, h))
+                if x as u64 + w as u64 > width as u64 || y as u64 + h as u64 > height as u64 =>
+            {
+                Err(PyValueError::new_err(format!(
+                    "Crop {:?} lies outside the {width}x{height} frame",
+                    (x, y, w, h)
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The crop, the whole `width` x `height` frame when there's none. See `check_frame`.
+    fn crop_rect(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        self.crop.unwrap_or((0, 0, width, height))
+    }
+
+    pub fn is_identity(&self) -> bool {
+        !self.flip_h
+            && !self.flip_v
+            && self.quarter_turns() == 0
+            && self.crop.is_none()
+            && self.resample.is_none()
+    }
+
+    /// Reframes an image of `channels` (1 or 3) interleaved samples per pixel.
+    pub fn apply<T: Sample>(
+        &self,
+        pixels: Vec<T>,
+        width: usize,
+        height: usize,
+        channels: usize,
+    ) -> Vec<T> {
+        if self.is_identity() {
+            return pixels;
+        }
+        let (x, y, w, h) = self.crop_rect(width as u32, height as u32);
+        let (w, h) = (w as usize, h as usize);
+        let pixels = match (w, h) == (width, height) {
+            true => pixels,
+            false => crop(&pixels, width, channels, (x as usize, y as usize, w, h)),
+        };
+        let pixels = self.orient(pixels, w, h, channels);
+        let (w, h) = self.rotated_size(w as u32, h as u32);
+        match self.resample {
+            Some(size) if size != (w, h) => {
+                T::resample(pixels, (w, h), channels, size, self.filter)
+            }
+            _ => pixels,
+        }
+    }
+
+    /// Applies only the flips and rotation.
+    pub fn orient<T: Copy>(
+        &self,
+        mut pixels: Vec<T>,
+        width: usize,
+        height: usize,
+        channels: usize,
+    ) -> Vec<T> {
+        // Half a turn is both flips.
+        let half_turn = self.quarter_turns() == 2;
+        if self.flip_v != half_turn {
+            flip_vertical(&mut pixels, width * channels, height);
+        }
+        if self.flip_h != half_turn {
+            for row in pixels.chunks_exact_mut(width * channels) {
+                for x in 0..width / 2 {
+                    for c in 0..channels {
+                        row.swap(x * channels + c, (width - 1 - x) * channels + c);
+                    }
+                }
+            }
+        }
+        match self.quarter_turns() {
+            1 => transpose(&pixels, width, height, channels, |x, y| (y, width - 1 - x)),
+            3 => transpose(&pixels, width, height, channels, |x, y| (height - 1 - y, x)),
+            _ => pixels,
+        }
+    }
+}
+
+/// Copies the (x, y, width, height) region out of an image `width` pixels wide.
+pub fn crop<T: Copy>(
+    pixels: &[T],
+    width: usize,
+    channels: usize,
+    (x, y, w, h): (usize, usize, usize, usize),
+) -> Vec<T> {
+    pixels
+        .chunks_exact(width * channels)
+        .skip(y)
+        .take(h)
+        .flat_map(|row| &row[x * channels..(x + w) * channels])
+        .copied()
+        .collect()
+}
+
+/// Sample types a frame can be resampled in.
+pub trait Sample: Copy {
+    fn resample(
+        pixels: Vec<Self>,
+        size: (u32, u32),
+        channels: usize,
+        new_size: (u32, u32),
+        filter: ResizeFilter,
+    ) -> Vec<Self>;
+}
+
+macro_rules! impl_sample {
+    ($($t:ty),*) => {$(
+        impl Sample for $t {
+            fn resample(
+                pixels: Vec<Self>,
+                size: (u32, u32),
+                channels: usize,
+                new_size: (u32, u32),
+                filter: ResizeFilter,
+            ) -> Vec<Self> {
+                resample(pixels, size, channels, new_size, filter)
+            }
+        }
+    )*};
+}
+
+impl_sample!(u8, u16, f32);
+
+fn resample<T>(
+    pixels: Vec<T>,
+    (width, height): (u32, u32),
+    channels: usize,
+    (new_width, new_height): (u32, u32),
+    filter: ResizeFilter,
+) -> Vec<T>
+where
+    T: Primitive + 'static,
+    Luma<T>: Pixel<Subpixel = T> + 'static,
+    Rgb<T>: Pixel<Subpixel = T> + 'static,
+{
+    let filter = FilterType::from(filter);
+    match channels {
+        1 => {
+            let image = ImageBuffer::<Luma<T>, _>::from_raw(width, height, pixels).unwrap();
+            imageops::resize(&image, new_width, new_height, filter).into_raw()
+        }
+        _ => {
+            let image = ImageBuffer::<Rgb<T>, _>::from_raw(width, height, pixels).unwrap();
+            imageops::resize(&image, new_width, new_height, filter).into_raw()
+        }
+    }
+}
+
+// Moves every pixel (x, y) to `to(x, y)` in an image `height` wide and `width` high.
+fn transpose<T: Copy>(
+    pixels: &[T],
+    width: usize,
+    height: usize,
+    channels: usize,
+    to: impl Fn(usize, usize) -> (usize, usize),
+) -> Vec<T> {
+    let mut out = pixels.to_vec();
+    for y in 0..height {
+        for x in 0..width {
+            let (out_x, out_y) = to(x, y);
+            let from = (y * width + x) * channels;
+            let dest = (out_y * height + out_x) * channels;
+            out[dest..dest + channels].copy_from_slice(&pixels[from..from + channels]);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3x2:
+    // 1 2 3
+    // 4 5 6
+    const IMAGE: [u16; 6] = [1, 2, 3, 4, 5, 6];
+
+    #[test]
+    fn test_flips_and_rotations() {
+        let apply = |flip_h, flip_v, rotate| {
+            Geometry::new(flip_h, flip_v, rotate, None, None, ResizeFilter::Nearest)
+                .unwrap()
+                .apply(IMAGE.to_vec(), 3, 2, 1)
+        };
+        assert_eq!(apply(true, false, 0), vec![3, 2, 1, 6, 5, 4]);
+        assert_eq!(apply(false, true, 0), vec![4, 5, 6, 1, 2, 3]);
+        assert_eq!(apply(false, false, 180), vec![6, 5, 4, 3, 2, 1]);
+        // Counter clockwise, the right column becomes the top row.
+        assert_eq!(apply(false, false, 90), vec![3, 6, 2, 5, 1, 4]);
+        assert_eq!(apply(false, false, -90), vec![4, 1, 5, 2, 6, 3]);
+        assert_eq!(apply(true, true, 180), IMAGE.to_vec());
+    }
+
+    #[test]
+    fn test_setters_validate() {
+        let mut geometry = Geometry::default();
+        assert!(geometry.set_rotate(45).is_err());
+        assert!(geometry.set_crop(Some((0, 0, 0, 2))).is_err());
+        assert!(geometry.set_resample(Some((4, 0))).is_err());
+        assert_eq!(geometry, Geometry::default());
+        geometry.set_rotate(-90).unwrap();
+        assert_eq!(geometry.rotate, -90);
+    }
+
+    #[test]
+    fn test_rgb_rotation() {
+        let rgb: Vec<u8> = (0..6).collect();
+        let geometry = Geometry::new(false, false, 270, None, None, ResizeFilter::Nearest).unwrap();
+        assert_eq!(geometry.output_size(2, 1), (1, 2));
+        assert_eq!(geometry.apply(rgb, 2, 1, 3), vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_crop_and_resample() {
+        let crop = Geometry {
+            crop: Some((1, 0, 2, 1)),
+            ..Default::default()
+        };
+        assert_eq!(crop.output_size(3, 2), (2, 1));
+        assert_eq!(crop.apply(IMAGE.to_vec(), 3, 2, 1), vec![2, 3]);
+        assert!(crop.check_frame(3, 2).is_ok());
+        assert!(crop.check_frame(2, 2).is_err());
+
+        let mut setup = cine::Setup::new(3, 2, 12, 1000);
+        setup.EnableCrop = 1;
+        // Clipped to the setup's frame.
+        setup.CropRect = cine::Rect {
+            left: 0,
+            top: 0,
+            right: 2,
+            bottom: 5,
+        };
+        setup.EnableResample = 1;
+        setup.ResampleWidth = 4;
+        setup.ResampleHeight = 4;
+        let framing = Geometry::from_setup_framing(&setup, ResizeFilter::Nearest);
+        assert_eq!(framing.output_size(3, 2), (4, 4));
+        assert_eq!(
+            framing.apply(IMAGE.to_vec(), 3, 2, 1),
+            vec![1, 1, 2, 2, 1, 1, 2, 2, 4, 4, 5, 5, 4, 4, 5, 5]
+        );
+    }
+}
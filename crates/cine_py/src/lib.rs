@@ -1,9 +1,25 @@
 use pyo3::prelude::*;
+pub mod cache;
+pub mod calibration;
 pub mod cine;
+pub mod color;
 pub mod conversions;
 pub mod file;
+pub mod geometry;
 pub mod lut;
+pub mod merge;
+pub mod pipeline;
+pub mod preview;
+pub mod render;
+pub mod stats;
 pub mod decompress;
+pub mod defects;
+pub mod export;
+pub mod video;
+pub mod writer;
+
+#[cfg(test)]
+mod testing;
 
 // Formats the sum of two numbers as string.
 #[pyfunction]
@@ -15,6 +31,22 @@ fn sum_as_string(a: usize, b: usize) -> PyResult<String> {
 #[pymodule]
 fn cine_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<file::CineFile>()?;
+    m.add_class::<cine::SetupRevision>()?;
+    m.add_class::<cine::Setup>()?;
+    m.add_class::<cine::Time64>()?;
+    m.add_class::<color::ColorSpace>()?;
+    m.add_class::<render::DisplayRender>()?;
+    m.add_class::<geometry::Geometry>()?;
+    m.add_class::<geometry::ResizeFilter>()?;
+    m.add_class::<pipeline::Pipeline>()?;
+    m.add_class::<calibration::CalibrationFrame>()?;
+    m.add_class::<calibration::StackMethod>()?;
+    m.add_class::<defects::DefectMap>()?;
+    m.add_class::<defects::DefectKind>()?;
+    m.add_class::<stats::FrameStats>()?;
+    m.add_class::<stats::FrameSummary>()?;
+    m.add_class::<writer::CineWriter>()?;
     m.add_function(wrap_pyfunction!(sum_as_string, m)?)?;
+    m.add_function(wrap_pyfunction!(merge::merge, m)?)?;
     Ok(())
 }
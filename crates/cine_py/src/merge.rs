@@ -0,0 +1,188 @@
+// Joins consecutive Cine files from the same camera, e.g. the segments of a multi-cine recording,
+// into one continuous file. Frames are copied as stored, nothing is decoded.
+
+use crate::cine;
+use crate::file::CineFile;
+use crate::writer::CineWriter;
+use pyo3::exceptions::{PyUserWarning, PyValueError};
+use pyo3::prelude::*;
+use std::ffi::CString;
+
+/// Returns the name of the first header or setup field that stops two files being merged.
+pub fn check_compatible(first: &CineFile, other: &CineFile) -> Result<(), &'static str> {
+    let (a, b) = (&first.bitmap_info_header, &other.bitmap_info_header);
+    let (sa, sb) = (&first.setup, &other.setup);
+    if a.bi_width != b.bi_width || sa.ImWidth != sb.ImWidth {
+        return Err("width");
+    }
+    if a.bi_height != b.bi_height || sa.ImHeight != sb.ImHeight {
+        return Err("height");
+    }
+    if a.bi_compression != b.bi_compression {
+        return Err("bi_compression");
+    }
+    if a.bi_size_image != b.bi_size_image {
+        return Err("bi_size_image");
+    }
+    if a.bi_bit_count != b.bi_bit_count {
+        return Err("bi_bit_count");
+    }
+    if sa.Serial != sb.Serial {
+        return Err("Serial");
+    }
+    if sa.CFA != sb.CFA {
+        return Err("CFA");
+    }
+    if sa.RealBPP != sb.RealBPP {
+        return Err("RealBPP");
+    }
+    Ok(())
+}
+
+/// Merges `paths`, in order, into a single Cine at `out_path`. Frames are renumbered from the first
+/// file's first image and keep their recorded times; frames without a time block continue on from
+/// the previous frame at the recording frame rate. Tagged blocks that aren't per image blocks
+/// in every file are left out with a warning.
+#[pyfunction]
+pub fn merge(py: Python<'_>, paths: Vec<String>, out_path: &str) -> PyResult<()> {
+    if paths.is_empty() {
+        return Err(PyValueError::new_err("Nothing to merge"));
+    }
+    let mut files = paths
+        .iter()
+        .map(|path| CineFile::new(path))
+        .collect::<PyResult<Vec<CineFile>>>()?;
+    for (path, file) in paths.iter().zip(&files).skip(1) {
+        check_compatible(&files[0], file).map_err(|field| {
+            PyValueError::new_err(format!("{path} doesn't match {} in {field}", paths[0]))
+        })?;
+    }
+
+    let first = &files[0];
+    let image_count: u32 = files.iter().map(|f| f.cine_file_header.image_count).sum();
+    let mut writer = CineWriter::new(
+        out_path,
+        first.setup,
+        image_count,
+        first.compression_type.bit_depth(),
+        first.cine_file_header.first_image_no,
+        Some(first.cine_file_header.trigger_time),
+    )?;
+
+    // Per image blocks are joined when every file has them, anything else can't be kept consistent.
+    let mut block_types: Vec<u16> = Vec::new();
+    for block in files.iter().flat_map(|f| &f.tagged_blocks) {
+        if block.block_type != cine::TAG_TIME_ONLY
+            && block.block_type != cine::TAG_EXPOSURE_ONLY
+            && !block_types.contains(&block.block_type)
+        {
+            block_types.push(block.block_type);
+        }
+    }
+    let mut dropped = Vec::new();
+    for block_type in block_types {
+        let parts: Option<Vec<&cine::TaggedBlock>> = files
+            .iter()
+            .map(|f| {
+                f.tagged_block(block_type)
+                    .filter(|b| b.is_per_image(f.cine_file_header.image_count as usize))
+            })
+            .collect();
+        match parts {
+            Some(parts) => {
+                let data = parts.iter().flat_map(|b| b.data.iter().copied()).collect();
+                writer.add_tagged_block(block_type, data)?;
+            }
+            None => dropped.push(block_type),
+        }
+    }
+    if !dropped.is_empty() {
+        let message = format!(
+            "Tagged blocks {dropped:?} aren't per image blocks in every file and are left out"
+        );
+        PyErr::warn(
+            py,
+            &py.get_type::<PyUserWarning>(),
+            &CString::new(message)?,
+            1,
+        )?;
+    }
+
+    let frame_period = 1.0 / first.setup.frame_rate();
+    let mut last_time = first.cine_file_header.trigger_time.as_secs_f64()
+        + (first.cine_file_header.first_image_no as f64 - 1.0) * frame_period;
+    for file in files.iter_mut() {
+        for frame_no in 0..file.cine_file_header.image_count as usize {
+            let time = file
+                .get_frame_time(frame_no)
+                .unwrap_or_else(|| cine::Time64::from_secs_f64(last_time + frame_period));
+            last_time = time.as_secs_f64();
+
+            let exposure = file.get_frame_exposure(frame_no);
+            let (annotation, pixels) = file.read_annotated_image(frame_no)?;
+            writer.write_raw_frame(&pixels, annotation, Some(time), exposure)?;
+        }
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFile;
+    use pyo3::exceptions::PyIOError;
+
+    fn write_segment(name: &str, setup: cine::Setup, first_image_no: i32, value: u16) -> TempFile {
+        let file = TempFile::new(name);
+        let mut writer = CineWriter::new(file.path(), setup, 2, 10, first_image_no, None).unwrap();
+        // Only the first segment has range data.
+        if first_image_no == 0 {
+            writer
+                .add_tagged_block(cine::TAG_RANGE_DATA, vec![0; 8])
+                .unwrap();
+        }
+        for _ in 0..2 {
+            writer.write_frame(vec![value; 16], None, None).unwrap();
+        }
+        writer.finish().unwrap();
+        file
+    }
+
+    #[test]
+    fn test_merge_segments() {
+        let setup = cine::Setup::new(8, 2, 10, 100);
+        let mut other_camera = setup;
+        other_camera.Serial = 42;
+        let files = [
+            write_segment("cine_py_merge_a.cine", setup, 0, 1),
+            write_segment("cine_py_merge_b.cine", setup, 2, 2),
+            write_segment("cine_py_merge_c.cine", other_camera, 4, 3),
+            TempFile::new("cine_py_merge_out.cine"),
+        ];
+        let paths: Vec<String> = files.iter().map(|f| f.path().to_owned()).collect();
+
+        Python::with_gil(|py| {
+            assert!(merge(py, vec![paths[0].clone(), paths[2].clone()], &paths[3]).is_err());
+            let missing = TempFile::new("cine_py_merge_missing.cine");
+            let err = merge(py, vec![paths[0].clone(), missing.path().into()], &paths[3]);
+            assert!(err.unwrap_err().is_instance_of::<PyIOError>(py));
+            merge(py, paths[0..2].to_vec(), &paths[3]).unwrap();
+        });
+
+        // Mismatches name the field that differs.
+        let first = files[0].open();
+        assert_eq!(check_compatible(&first, &files[2].open()), Err("Serial"));
+        let mut padded = files[1].open();
+        padded.bitmap_info_header.bi_size_image += 5;
+        assert_eq!(check_compatible(&first, &padded), Err("bi_size_image"));
+
+        let mut merged = files[3].open();
+        assert_eq!(merged.cine_file_header.image_count, 4);
+        assert_eq!(merged.cine_file_header.first_image_no, 0);
+        assert_eq!(merged.get_sensor_frame(3).unwrap(), vec![2; 16]);
+        assert!(merged.tagged_block(cine::TAG_RANGE_DATA).is_none());
+        let t1 = merged.get_frame_time(1).unwrap().as_secs_f64();
+        let t2 = merged.get_frame_time(2).unwrap().as_secs_f64();
+        assert!((t2 - t1 - 0.01).abs() < 1e-6);
+    }
+}
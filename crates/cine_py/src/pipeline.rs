@@ -0,0 +1,442 @@
+// A configurable chain of the processing stages, so one file reader can give different
+// renditions of the same frames.
+
+use crate::calibration::CalibrationFrame;
+use crate::cine;
+use crate::color::{ColorCorrection, ColorSpace};
+use crate::conversions::shift_pattern;
+use crate::decompress::Decompression;
+use crate::file::CineFile;
+use crate::geometry::{self, Geometry};
+use crate::render::{DisplayRender, tone_curve};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+/// The stages turning sensor values into a frame. Unpacking (with the file's defect map, see
+/// `CineFile.set_defect_map`) always runs, the other stages run in the order of the fields and
+/// each can be switched off on its own. Values are floats, normalised to black 0.0 / white 1.0
+/// when the black subtraction is on. Get the setup's with `CineFile.pipeline()`, change it and
+/// hand it back with `CineFile.set_pipeline()`.
+#[pyclass(module = "cinepy", get_all, set_all)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    /// Stored 10-bit values through the 10 to 12-bit LUT, off keeps them on the stored curve.
+    pub linearise: bool,
+    /// Dark reference in linear sensor values (see `CineFile.build_dark_frame`), taking out the
+    /// fixed pattern above `BlackLevel` so the black subtraction still lines up. Needs
+    /// `linearise`. `None` is off.
+    pub dark_frame: Option<CalibrationFrame>,
+    /// Subtracts `BlackLevel` and scales `WhiteLevel` to 1.0. Off scales the whole code range to
+    /// 0..1 instead.
+    pub black_subtract: bool,
+    /// Per pixel gains multiplied onto the mosaic (see `CineFile.build_flat_field`). `None` is
+    /// off.
+    pub flat_field: Option<CalibrationFrame>,
+    /// Bilinear demosaic of colour sensors, off keeps the single channel mosaic.
+    pub demosaic: bool,
+    /// `WBGain` and `fGainR/G/B`, on each colour of the mosaic when it isn't demosaiced.
+    pub white_balance: bool,
+    /// Space the colour matrices convert to, `Camera` leaves out the matrix stage. Only demosaiced
+    /// frames go through it.
+    pub color_space: ColorSpace,
+    /// Per channel gamma, `fGamma` with the `fGammaR`/`fGammaB` deltas. `None` is off.
+    pub gamma: Option<[f32; 3]>,
+    /// Points of the `fTone` curve, applied after the gamma like Phantom software does. Empty is
+    /// off.
+    pub tone: Vec<(f32, f32)>,
+    /// Crop, flips, rotation and resample; an identity geometry is off.
+    pub geometry: Geometry,
+}
+
+#[pymethods]
+impl Pipeline {
+    /// A pipeline that only unpacks, linearises, normalises and demosaics.
+    #[new]
+    pub fn new() -> Self {
+        Self {
+            linearise: true,
+            dark_frame: None,
+            black_subtract: true,
+            flat_field: None,
+            demosaic: true,
+            white_balance: false,
+            color_space: ColorSpace::Camera,
+            gamma: None,
+            tone: Vec::new(),
+            geometry: Geometry::default(),
+        }
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pipeline {
+    /// The setup's processing, white balanced and converted to linear sRGB, reframed with
+    /// `geometry`. Values stay proportional to the light unless `render` adds the setup's gamma
+    /// and `fTone` curve, the way PCC renders it.
+    pub fn from_setup(
+        setup: &cine::Setup,
+        compression: &Decompression,
+        geometry: Geometry,
+        render: bool,
+    ) -> Self {
+        let (gamma, tone) = match render {
+            true => {
+                let render = DisplayRender::from_setup(setup, compression);
+                (render.gamma, render.tone)
+            }
+            false => (None, Vec::new()),
+        };
+        Self {
+            white_balance: true,
+            color_space: ColorSpace::LinearSrgb,
+            gamma,
+            tone,
+            geometry,
+            ..Self::new()
+        }
+    }
+
+    /// Samples per pixel of the output for a sensor with the given CFA `pattern`.
+    pub fn channels(&self, pattern: Option<[u8; 4]>) -> usize {
+        match pattern {
+            Some(_) if self.demosaic => 3,
+            _ => 1,
+        }
+    }
+
+    /// Runs the stages on a frame, returning the pixels and the number of channels.
+    pub fn process(
+        &self,
+        cine_file: &mut CineFile,
+        frame_no: usize,
+    ) -> PyResult<(Vec<f32>, usize)> {
+        let sensor = cine_file.decoded_frame(frame_no)?;
+        let width = cine_file.bitmap_info_header.bi_width as usize;
+        let height = cine_file.bitmap_info_header.bi_height as usize;
+        let channels = self.channels(cine_file.cfa.pattern());
+        let mut pixels = vec![0.0; width * height * channels];
+        let frame = (0, 0, width, height);
+        self.develop_into(cine_file, &sensor, frame, &mut Vec::new(), &mut pixels)?;
+        Ok((
+            self.geometry.apply(pixels, width, height, channels),
+            channels,
+        ))
+    }
+
+    /// `process` into `out`, which must be as long as the output. The sensor values and the
+    /// mosaic go through the file handle's own buffers, so nothing is allocated per frame unless
+    /// the geometry changes the frame.
+    pub fn process_into(
+        &self,
+        cine_file: &mut CineFile,
+        frame_no: usize,
+        out: &mut [f32],
+    ) -> PyResult<()> {
+        let width = cine_file.bitmap_info_header.bi_width as usize;
+        let height = cine_file.bitmap_info_header.bi_height as usize;
+        let channels = self.channels(cine_file.cfa.pattern());
+        let (out_width, out_height) = self.geometry.output_size(width as u32, height as u32);
+        if out.len() != out_width as usize * out_height as usize * channels {
+            return Err(PyValueError::new_err(format!(
+                "Output holds {} values, the frame has {}",
+                out.len(),
+                out_width as usize * out_height as usize * channels
+            )));
+        }
+
+        let frame = (0, 0, width, height);
+        let (mut sensor, mut mosaic) = cine_file.take_buffers();
+        let result = cine_file.decode_into(frame_no, &mut sensor).and_then(|_| {
+            if self.geometry.is_identity() {
+                return self.develop_into(cine_file, &sensor, frame, &mut mosaic, out);
+            }
+            let mut pixels = vec![0.0; width * height * channels];
+            self.develop_into(cine_file, &sensor, frame, &mut mosaic, &mut pixels)?;
+            out.copy_from_slice(&self.geometry.apply(pixels, width, height, channels));
+            Ok(())
+        });
+        cine_file.restore_buffers(sensor, mosaic);
+        result
+    }
+
+    /// `process` of the (x, y, width, height) region of a frame in stored frame coordinates,
+    /// oriented by the geometry but not cropped or resampled. Unless a defect map is set only the
+    /// region is read and processed, grown to whole CFA cells and a cell of border all round
+    /// when it's demosaiced so every pixel in it sees the same neighbours as in the full frame.
+    pub fn process_region(
+        &self,
+        cine_file: &mut CineFile,
+        frame_no: usize,
+        (x, y, w, h): (usize, usize, usize, usize),
+    ) -> PyResult<(Vec<f32>, usize)> {
+        let channels = self.channels(cine_file.cfa.pattern());
+        let width = cine_file.bitmap_info_header.bi_width as usize;
+        let height = cine_file.bitmap_info_header.bi_height as usize;
+        let grown = match channels {
+            3 => {
+                let (x0, y0) = ((x & !1).saturating_sub(2), (y & !1).saturating_sub(2));
+                let x1 = ((x + w).next_multiple_of(2) + 2).min(width);
+                let y1 = ((y + h).next_multiple_of(2) + 2).min(height);
+                (x0, y0, x1 - x0, y1 - y0)
+            }
+            _ => (x, y, w, h),
+        };
+        // Defects are filled in from outside the region.
+        let sensor = match cine_file.defect_map {
+            None => cine_file.read_sensor_region(frame_no, grown)?,
+            Some(_) => None,
+        };
+        let (window, sensor) = match sensor {
+            Some(sensor) => (grown, sensor),
+            None => ((0, 0, width, height), cine_file.decoded_frame(frame_no)?),
+        };
+        let mut pixels = vec![0.0; window.2 * window.3 * channels];
+        self.develop_into(cine_file, &sensor, window, &mut Vec::new(), &mut pixels)?;
+        let pixels = match window == (x, y, w, h) {
+            true => pixels,
+            false => geometry::crop(
+                &pixels,
+                window.2,
+                channels,
+                (x - window.0, y - window.1, w, h),
+            ),
+        };
+        Ok((self.geometry.orient(pixels, w, h, channels), channels))
+    }
+
+    // The stages before the geometry on `sensor`, the (x, y, width, height) window of a frame,
+    // into `out`. `mosaic` holds the normalised mosaic when it's demosaiced.
+    fn develop_into(
+        &self,
+        cine_file: &CineFile,
+        sensor: &[u16],
+        window: (usize, usize, usize, usize),
+        mosaic: &mut Vec<f32>,
+        out: &mut [f32],
+    ) -> PyResult<()> {
+        let (x0, y0, width, _) = window;
+        let setup = &cine_file.setup;
+        let correction = ColorCorrection::from_setup(setup, self.color_space, self.white_balance);
+        let channels = match cine_file.cfa.pattern() {
+            Some(_) if self.demosaic => {
+                mosaic.resize(sensor.len(), 0.0);
+                self.normalise_into(cine_file, sensor, window, mosaic)?;
+                cine_file
+                    .cfa
+                    .demosaic_into(mosaic, width, (x0, y0), out)
+                    .map_err(|_| PyValueError::new_err("Failed to demosaic frame"))?;
+                correction.apply(out);
+                3
+            }
+            Some(pattern) => {
+                self.normalise_into(cine_file, sensor, window, out)?;
+                if self.white_balance {
+                    let pattern = shift_pattern(pattern, x0, y0);
+                    for (i, v) in out.iter_mut().enumerate() {
+                        let (x, y) = (i % width, i / width);
+                        *v *= correction.gains[pattern[(y % 2) * 2 + x % 2] as usize];
+                    }
+                }
+                1
+            }
+            None => {
+                self.normalise_into(cine_file, sensor, window, out)?;
+                1
+            }
+        };
+
+        if let Some(gamma) = self.gamma {
+            for (i, v) in out.iter_mut().enumerate() {
+                // Single channel frames take the green gamma.
+                let channel = if channels == 3 { i % 3 } else { 1 };
+                *v = v.max(0.0).powf(1.0 / gamma[channel]);
+            }
+        }
+        if !self.tone.is_empty() {
+            out.iter_mut().for_each(|v| *v = tone_curve(&self.tone, *v));
+        }
+        Ok(())
+    }
+
+    // Linearises, normalises and calibrates `sensor` into `values`, as `develop_into`.
+    // Calibration frames line up with where the window sits in the frame.
+    fn normalise_into(
+        &self,
+        cine_file: &CineFile,
+        sensor: &[u16],
+        (x0, y0, width, _): (usize, usize, usize, usize),
+        values: &mut [f32],
+    ) -> PyResult<()> {
+        let setup = &cine_file.setup;
+        let compression = &cine_file.compression_type;
+        let frame_width = cine_file.bitmap_info_header.bi_width as usize;
+        let frame_len = frame_width * cine_file.bitmap_info_header.bi_height as usize;
+        // Index in the full frame of the window's `i`th pixel.
+        let frame_index = |i: usize| (y0 + i / width) * frame_width + x0 + i % width;
+
+        let value = |p: u16| match self.linearise {
+            true => compression.to_linear(p) as f32,
+            false => p as f32,
+        };
+        let (black, white) = match self.black_subtract {
+            true => (
+                value(setup.black_level() as u16),
+                value(setup.white_level() as u16),
+            ),
+            false => (0.0, value(((1u32 << compression.bit_depth()) - 1) as u16)),
+        };
+        let range = (white - black).max(1.0);
+        values
+            .iter_mut()
+            .zip(sensor)
+            .for_each(|(v, p)| *v = (value(*p) - black) / range);
+
+        if let Some(dark) = &self.dark_frame {
+            if !self.linearise {
+                return Err(PyValueError::new_err(
+                    "Dark frame subtraction needs the linearise stage",
+                ));
+            }
+            if dark.pixels.len() != frame_len {
+                return Err(PyValueError::new_err(format!(
+                    "Dark frame has {} pixels, the frame has {frame_len}",
+                    dark.pixels.len()
+                )));
+            }
+            let level = value(setup.black_level() as u16);
+            for (i, v) in values.iter_mut().enumerate() {
+                *v -= (dark.pixels[frame_index(i)] - level) / range;
+            }
+        }
+
+        if let Some(flat) = &self.flat_field {
+            if flat.pixels.len() != frame_len {
+                return Err(PyValueError::new_err(format!(
+                    "Flat field has {} gains, the frame has {frame_len} pixels",
+                    flat.pixels.len()
+                )));
+            }
+            for (i, v) in values.iter_mut().enumerate() {
+                *v *= flat.pixels[frame_index(i)];
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A 0..1 value as a full range 16-bit one, what `get_frame` returns for `uint16`.
+pub fn quantise(v: f32) -> u16 {
+    (v.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::StackMethod;
+    use crate::testing::write_cine;
+
+    #[test]
+    fn test_stages() {
+        let mut setup = cine::Setup::new(4, 4, 12, 100);
+        setup.CFA = 3;
+        setup.fGainR = 2.0;
+        let file = write_cine("cine_py_pipeline.cine", setup, &[vec![2047; 16]]);
+        let mut cine_file = file.open();
+        let half = 2047.0 / 4095.0;
+
+        // gb/rg, red sits at odd rows and even columns.
+        let mut pipeline = Pipeline {
+            demosaic: false,
+            white_balance: true,
+            ..Pipeline::new()
+        };
+        let (mosaic, channels) = pipeline.process(&mut cine_file, 0).unwrap();
+        assert_eq!(channels, 1);
+        assert_eq!(mosaic[4], 2.0 * half);
+        assert_eq!(mosaic[5], half);
+
+        pipeline.flat_field = Some(CalibrationFrame::new(4, 4, vec![0.5; 16]).unwrap());
+        let (mosaic, _) = pipeline.process(&mut cine_file, 0).unwrap();
+        assert_eq!(mosaic[4], half);
+        pipeline.flat_field = Some(CalibrationFrame::new(3, 5, vec![0.5; 15]).unwrap());
+        assert!(pipeline.process(&mut cine_file, 0).is_err());
+
+        let pipeline = Pipeline {
+            gamma: Some([2.0; 3]),
+            geometry: Geometry {
+                crop: Some((1, 1, 2, 2)),
+                ..Default::default()
+            },
+            ..Pipeline::new()
+        };
+        let (rgb, channels) = pipeline.process(&mut cine_file, 0).unwrap();
+        assert_eq!((rgb.len(), channels), (12, 3));
+        assert!(rgb.iter().all(|v| (v - half.sqrt()).abs() < 1e-6));
+
+        // The file's processing follows a pipeline set on it.
+        Python::with_gil(|py| cine_file.set_pipeline(py, Some(pipeline))).unwrap();
+        assert_eq!(cine_file.frame_shape(), (2, 2, 3));
+        let frame = cine_file.get_frame(0, None).unwrap();
+        assert_eq!(frame, vec![(half.sqrt() * 65535.0).round() as u16; 12]);
+    }
+
+    #[test]
+    fn test_dark_frame() {
+        let mut setup = cine::Setup::new(4, 4, 12, 100);
+        setup.BlackLevel = 100;
+        // Two capped frames with a fixed pattern, then a lit one on top of the same pattern.
+        let frames: Vec<Vec<u16>> = [100, 102, 1101]
+            .iter()
+            .map(|offset| (0..16).map(|i| i + offset).collect())
+            .collect();
+        let file = write_cine("cine_py_dark.cine", setup, &frames);
+        let mut cine_file = file.open();
+        let dark = cine_file.build_dark_frame(0, 2, StackMethod::Mean).unwrap();
+        assert_eq!(dark.pixels[5], 106.0);
+        let pipeline = Pipeline {
+            dark_frame: Some(dark),
+            ..Pipeline::new()
+        };
+        let (frame, _) = pipeline.process(&mut cine_file, 2).unwrap();
+        let expected = 1000.0 / (4095.0 - 100.0);
+        assert!(frame.iter().all(|v| (v - expected).abs() < 1e-6));
+
+        let pipeline = Pipeline {
+            linearise: false,
+            ..pipeline
+        };
+        assert!(pipeline.process(&mut cine_file, 2).is_err());
+        assert!(!cine_file.black_reference_applied());
+    }
+
+    #[test]
+    fn test_flat_field() {
+        // Every other pixel gets half the light, in the flat and the scene alike.
+        let response = |i: u16| if i.is_multiple_of(2) { 1000 } else { 500 };
+        let frames: Vec<Vec<u16>> = [1, 2]
+            .iter()
+            .map(|scale| (0..16).map(|i| response(i) * scale).collect())
+            .collect();
+        let file = write_cine(
+            "cine_py_flat.cine",
+            cine::Setup::new(4, 4, 12, 100),
+            &frames,
+        );
+        let mut cine_file = file.open();
+        let flat = cine_file
+            .build_flat_field(0, 1, None, StackMethod::Mean, 0.1)
+            .unwrap();
+        assert_eq!(&flat.pixels[..2], &[0.75, 1.5]);
+        let pipeline = Pipeline {
+            flat_field: Some(flat),
+            ..Pipeline::new()
+        };
+        let (frame, _) = pipeline.process(&mut cine_file, 1).unwrap();
+        assert!(frame.iter().all(|v| (v - 1500.0 / 4095.0).abs() < 1e-6));
+    }
+}
@@ -0,0 +1,193 @@
+// Small looping previews of a recording, as animated GIF or APNG, and labelled contact sheets.
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::imageops::{self, FilterType};
+use image::{Delay, Frame, ImageResult, RgbImage, buffer::ConvertBuffer};
+use std::time::Duration;
+
+pub enum PreviewFormat {
+    Gif,
+    Apng,
+}
+
+impl PreviewFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "gif" => Some(PreviewFormat::Gif),
+            "apng" | "png" => Some(PreviewFormat::Apng),
+            _ => None,
+        }
+    }
+}
+
+/// The size of a `width` x `height` image scaled down so its longest edge fits `max_size`.
+pub fn fit_size(width: u32, height: u32, max_size: u32) -> (u32, u32) {
+    let longest = width.max(height);
+    if longest <= max_size {
+        return (width, height);
+    }
+    let scale = |v: u32| ((v as u64 * max_size as u64) / longest as u64).max(1) as u32;
+    (scale(width), scale(height))
+}
+
+pub fn resize_rgb(image: RgbImage, width: u32, height: u32) -> RgbImage {
+    if image.dimensions() == (width, height) {
+        return image;
+    }
+    imageops::resize(&image, width, height, FilterType::Triangle)
+}
+
+/// Encodes RGB frames as an endlessly looping animation with a fixed delay between frames.
+pub fn encode_animation(
+    format: &PreviewFormat,
+    frames: &[RgbImage],
+    delay: Duration,
+) -> ImageResult<Vec<u8>> {
+    match format {
+        PreviewFormat::Gif => encode_gif(frames, delay),
+        PreviewFormat::Apng => encode_apng(frames, delay),
+    }
+}
+
+fn encode_gif(frames: &[RgbImage], delay: Duration) -> ImageResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    {
+        // Speed 10 is the quantiser's suggested trade off between colour quality and time.
+        let mut encoder = GifEncoder::new_with_speed(&mut bytes, 10);
+        encoder.set_repeat(Repeat::Infinite)?;
+        encoder.encode_frames(frames.iter().map(|rgb| {
+            Frame::from_parts(rgb.convert(), 0, 0, Delay::from_saturating_duration(delay))
+        }))?;
+    }
+    Ok(bytes)
+}
+
+fn encode_apng(frames: &[RgbImage], delay: Duration) -> ImageResult<Vec<u8>> {
+    let (width, height) = frames[0].dimensions();
+    let mut bytes = Vec::new();
+    let to_image_error = |e: png::EncodingError| {
+        image::ImageError::Encoding(image::error::EncodingError::new(
+            image::ImageFormat::Png.into(),
+            e,
+        ))
+    };
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        // 0 plays repeatedly.
+        encoder
+            .set_animated(frames.len() as u32, 0)
+            .map_err(to_image_error)?;
+        let delay_ms = delay.as_millis().min(u16::MAX as u128) as u16;
+        encoder
+            .set_frame_delay(delay_ms, 1000)
+            .map_err(to_image_error)?;
+        let mut writer = encoder.write_header().map_err(to_image_error)?;
+        for rgb in frames {
+            writer.write_image_data(rgb).map_err(to_image_error)?;
+        }
+        writer.finish().map_err(to_image_error)?;
+    }
+    Ok(bytes)
+}
+
+// 3x5 pixel glyphs for burning frame numbers and times into images, one row of 3 bits per byte.
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        's' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        _ => [0; 5],
+    }
+}
+
+/// Height of a text band drawn by `draw_label` at the given scale.
+pub fn label_height(scale: u32) -> u32 {
+    (GLYPH_HEIGHT + 2) * scale
+}
+
+/// Draws white `text` on a black band across the bottom of `image`.
+pub fn draw_label(image: &mut RgbImage, text: &str, scale: u32) {
+    let (width, height) = image.dimensions();
+    let band = label_height(scale).min(height);
+    for y in height - band..height {
+        for x in 0..width {
+            image.put_pixel(x, y, image::Rgb([0, 0, 0]));
+        }
+    }
+    let top = height - band + scale;
+    for (i, c) in text.chars().enumerate() {
+        let left = scale + i as u32 * (GLYPH_WIDTH + 1) * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits >> (GLYPH_WIDTH - 1 - col) & 1 == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let (x, y) = (left + col * scale + dx, top + row as u32 * scale + dy);
+                        if x < width && y < height {
+                            image.put_pixel(x, y, image::Rgb([255, 255, 255]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_size() {
+        assert_eq!(fit_size(2048, 1080, 256), (256, 135));
+        assert_eq!(fit_size(100, 400, 200), (50, 200));
+        assert_eq!(fit_size(64, 32, 256), (64, 32));
+    }
+
+    #[test]
+    fn test_draw_label() {
+        let mut image = RgbImage::from_pixel(20, 10, image::Rgb([90, 90, 90]));
+        draw_label(&mut image, "1", 1);
+        // The band is black with the glyph's top row lit at (1..4, 4).
+        assert_eq!(image.get_pixel(0, 3).0, [0, 0, 0]);
+        assert_eq!(image.get_pixel(2, 4).0, [255, 255, 255]);
+        assert_eq!(image.get_pixel(1, 4).0, [0, 0, 0]);
+        assert_eq!(image.get_pixel(0, 2).0, [90, 90, 90]);
+    }
+
+    #[test]
+    fn test_apng_frames() {
+        let frames: Vec<RgbImage> = (0..3)
+            .map(|v| RgbImage::from_pixel(8, 4, image::Rgb([v * 80, 0, 0])))
+            .collect();
+        let bytes =
+            encode_animation(&PreviewFormat::Apng, &frames, Duration::from_millis(40)).unwrap();
+
+        let decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+        let reader = decoder.read_info().unwrap();
+        let animation = reader.info().animation_control().unwrap();
+        assert_eq!(animation.num_frames, 3);
+        assert_eq!(animation.num_plays, 0);
+
+        let gif =
+            encode_animation(&PreviewFormat::Gif, &frames, Duration::from_millis(40)).unwrap();
+        assert_eq!(&gif[..6], b"GIF89a");
+    }
+}
@@ -0,0 +1,265 @@
+// Turns linear frames into 8-bit renditions for viewing and sharing, following the image
+// processing values Phantom software stores in the setup.
+
+use crate::cine;
+use crate::decompress::Decompression;
+use pyo3::prelude::*;
+
+// Rec.709 luma weights, the colour adjustments keep luma unchanged.
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// The display processing of a setup. Stages run in the order of the fields, on linear values
+/// normalised to black 0.0 / white 1.0. Get one with `CineFile.display_settings()`, change it and
+/// hand it back with `CineFile.set_display_settings()` to override the setup.
+#[pyclass(module = "cinepy", get_all, set_all)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayRender {
+    /// Input range stretched to the full output, from `Conv8Min`/`Conv8Max`.
+    pub window: (f32, f32),
+    /// `fFlare`, subtracted with the remaining range stretched back to 0..1.
+    pub flare: f32,
+    /// `fGain` and `fOffset`, contrast and brightness.
+    pub gain: f32,
+    pub offset: f32,
+    /// `fPedestalR/G/B`, lifted onto each channel.
+    pub pedestal: [f32; 3],
+    /// Turns the saturation, hue and chroma stages on or off.
+    pub adjust_color: bool,
+    /// `fSaturation` (or the older `Saturation` percentage), scales colour in linear light.
+    pub saturation: f32,
+    /// `fHue` (or the older `Hue`), rotation of the colours around the grey axis in degrees.
+    pub hue: f32,
+    /// Per channel gamma, `fGamma` with the `fGammaR`/`fGammaB` deltas. `None` when the setup has
+    /// no gamma, those frames get the Rec.709 curve instead.
+    pub gamma: Option<[f32; 3]>,
+    /// `fChroma`, scales colour after the gamma.
+    pub chroma: f32,
+    /// Points of the `fTone` curve between the implied (0, 0) and (1, 1).
+    pub tone: Vec<(f32, f32)>,
+}
+
+impl Default for DisplayRender {
+    fn default() -> Self {
+        Self {
+            window: (0.0, 1.0),
+            flare: 0.0,
+            gain: 1.0,
+            offset: 0.0,
+            pedestal: [0.0; 3],
+            adjust_color: true,
+            saturation: 1.0,
+            hue: 0.0,
+            gamma: None,
+            chroma: 1.0,
+            tone: Vec::new(),
+        }
+    }
+}
+
+#[pymethods]
+impl DisplayRender {
+    /// Settings that only clip and apply the Rec.709 curve.
+    #[new]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl DisplayRender {
+    pub fn from_setup(setup: &cine::Setup, compression: &Decompression) -> Self {
+        let mut render = Self::default();
+
+        // Conv8Min/Conv8Max are stored sensor values, like BlackLevel/WhiteLevel.
+        if setup.Conv8Max > setup.Conv8Min {
+            let black = compression.to_linear(setup.black_level() as u16) as f32;
+            let white = compression.to_linear(setup.white_level() as u16) as f32;
+            let range = (white - black).max(1.0);
+            let level = |v: u32| {
+                (compression.to_linear(v.min(u16::MAX as u32) as u16) as f32 - black) / range
+            };
+            render.window = (level(setup.Conv8Min), level(setup.Conv8Max));
+        }
+        if setup.fFlare.abs() < 1.0 {
+            render.flare = setup.fFlare;
+        }
+        if setup.fGain > 0.0 {
+            render.gain = setup.fGain;
+        }
+        render.offset = setup.fOffset;
+        render.pedestal = [setup.fPedestalR, setup.fPedestalG, setup.fPedestalB];
+
+        // Zero means unset for the float fields, older setups only have the integer ones.
+        render.saturation = match setup.fSaturation {
+            s if s > 0.0 => s,
+            _ => (1.0 + setup.Saturation as f32 / 100.0).max(0.0),
+        };
+        render.hue = if setup.fHue != 0.0 {
+            setup.fHue
+        } else {
+            setup.Hue as f32
+        };
+        if setup.fChroma > 0.0 {
+            render.chroma = setup.fChroma;
+        }
+
+        if setup.fGamma > 0.0 {
+            let gamma = setup.fGamma;
+            render.gamma =
+                Some([gamma + setup.fGammaR, gamma, gamma + setup.fGammaB].map(|g| g.max(0.01)));
+        }
+        let points = (setup.TonePoints.max(0) as usize).min(setup.fTone.len() / 2);
+        render.tone = setup.fTone[..points * 2]
+            .chunks_exact(2)
+            .map(|p| (p[0], p[1]))
+            .collect();
+        render
+    }
+
+    /// Renders linear pixels (1 channel or interleaved RGB) to 8 bits.
+    pub fn render(&self, linear: &[f32], channels: usize) -> Vec<u8> {
+        let quantise = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        if channels != 3 {
+            // Single channel frames are processed as green, without the colour stages.
+            return linear
+                .iter()
+                .map(|v| quantise(self.encode(self.levels(*v, 1), 1)))
+                .collect();
+        }
+        linear
+            .chunks_exact(3)
+            .flat_map(|p| {
+                let mut rgb = [0, 1, 2].map(|c| self.levels(p[c], c));
+                if self.adjust_color {
+                    rgb = scale_color(rgb, self.saturation, self.hue).map(|v| v.clamp(0.0, 1.0));
+                }
+                rgb = [0, 1, 2].map(|c| self.encode(rgb[c], c));
+                if self.adjust_color && self.chroma != 1.0 {
+                    rgb = scale_color(rgb, self.chroma, 0.0);
+                }
+                rgb.map(quantise)
+            })
+            .collect()
+    }
+
+    // Window, flare, gain, offset and pedestal; linear in, clipped linear out.
+    fn levels(&self, v: f32, channel: usize) -> f32 {
+        let (lo, hi) = self.window;
+        let v = (v - lo) / (hi - lo).max(f32::EPSILON);
+        let v = (v - self.flare) / (1.0 - self.flare);
+        (v * self.gain + self.offset + self.pedestal[channel]).clamp(0.0, 1.0)
+    }
+
+    // Gamma (or Rec.709) then the tone curve.
+    fn encode(&self, v: f32, channel: usize) -> f32 {
+        let v = match self.gamma {
+            Some(gamma) => v.powf(1.0 / gamma[channel]),
+            None => rec709_oetf(v),
+        };
+        tone_curve(&self.tone, v)
+    }
+}
+
+// Scales and rotates the colour difference (R - Y, B - Y) of a pixel, leaving its luma alone.
+fn scale_color(rgb: [f32; 3], scale: f32, hue_degrees: f32) -> [f32; 3] {
+    if scale == 1.0 && hue_degrees == 0.0 {
+        return rgb;
+    }
+    let y = LUMA[0] * rgb[0] + LUMA[1] * rgb[1] + LUMA[2] * rgb[2];
+    let (cr, cb) = (rgb[0] - y, rgb[2] - y);
+    let (sin, cos) = hue_degrees.to_radians().sin_cos();
+    let cr_out = (cr * cos - cb * sin) * scale;
+    let cb_out = (cr * sin + cb * cos) * scale;
+    let r = y + cr_out;
+    let b = y + cb_out;
+    let g = (y - LUMA[0] * r - LUMA[2] * b) / LUMA[1];
+    [r, g, b]
+}
+
+// Piecewise linear through the points, with (0, 0) and (1, 1) at the ends.
+pub(crate) fn tone_curve(points: &[(f32, f32)], v: f32) -> f32 {
+    if points.is_empty() {
+        return v;
+    }
+    let mut previous = (0.0, 0.0);
+    for &(x, y) in points.iter().chain([(1.0, 1.0)].iter()) {
+        if v <= x {
+            let span = x - previous.0;
+            if span <= 0.0 {
+                return y;
+            }
+            return previous.1 + (y - previous.1) * (v - previous.0) / span;
+        }
+        previous = (x, y);
+    }
+    1.0
+}
+
+fn rec709_oetf(v: f32) -> f32 {
+    if v < 0.018 {
+        4.5 * v
+    } else {
+        1.099 * v.powf(0.45) - 0.099
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_render_clips() {
+        let render = DisplayRender::default();
+        assert_eq!(
+            render.render(&[-0.5, 0.0, 1.0, 4.0], 1),
+            vec![0, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_setup_render() {
+        let mut setup = cine::Setup::new(4, 2, 12, 1000);
+        setup.BlackLevel = 0;
+        setup.WhiteLevel = 4095;
+        setup.Conv8Min = 0;
+        setup.Conv8Max = 2047;
+        setup.fGamma = 1.0;
+        setup.fGammaB = 1.0;
+        setup.TonePoints = 1;
+        setup.fTone[..2].copy_from_slice(&[0.5, 0.25]);
+        let render = DisplayRender::from_setup(&setup, &Decompression::Packed12Bit);
+
+        // 0.25 of white is half the Conv8 window, the tone curve takes that down to 0.25.
+        let half = 2047.0 / 4095.0 / 2.0;
+        let rgb = render.render(&[half, half, half], 3);
+        assert_eq!(rgb[0], rgb[1]);
+        assert_eq!(rgb[1], 64);
+        // Blue has gamma 2, lifting it above the others.
+        assert!(rgb[2] > rgb[1]);
+    }
+
+    #[test]
+    fn test_color_adjustments() {
+        let mut setup = cine::Setup::new(4, 2, 12, 1000);
+        setup.fGamma = 1.0;
+        setup.fSaturation = 0.0;
+        setup.Saturation = -100;
+        let mut render = DisplayRender::from_setup(&setup, &Decompression::Packed12Bit);
+        assert_eq!(render.saturation, 0.0);
+
+        // No saturation leaves only the luma.
+        let grey = render.render(&[0.8, 0.4, 0.2], 3);
+        assert_eq!(grey[0], grey[1]);
+        assert_eq!(grey[1], grey[2]);
+
+        // Half a turn of hue swaps the sides of the grey axis, luma stays.
+        render.saturation = 1.0;
+        render.hue = 180.0;
+        let rotated = scale_color([0.6, 0.5, 0.4], render.saturation, render.hue);
+        let luma = |p: [f32; 3]| LUMA[0] * p[0] + LUMA[1] * p[1] + LUMA[2] * p[2];
+        assert!((luma(rotated) - luma([0.6, 0.5, 0.4])).abs() < 1e-6);
+        assert!(rotated[0] < rotated[2]);
+
+        render.adjust_color = false;
+        assert_ne!(render.render(&[0.8, 0.4, 0.2], 3), grey);
+    }
+}
@@ -0,0 +1,212 @@
+// Per pixel statistics over a range of frames, reduced one frame at a time so only the running
+// totals stay in memory, and per frame histograms and summaries. Partial results from frames
+// read in parallel merge into one.
+
+use pyo3::prelude::*;
+
+/// Per pixel mean, standard deviation, minimum and maximum of `count` frames, each `width` x
+/// `height` x `channels` values laid out like `get_frame`.
+#[pyclass(module = "cinepy", get_all)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameStats {
+    pub width: u32,
+    pub height: u32,
+    pub channels: usize,
+    pub count: usize,
+    pub mean: Vec<f32>,
+    /// Population standard deviation.
+    pub std: Vec<f32>,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+}
+
+/// Histograms and summary values of frames, one entry per frame in `frames`. Values are linear,
+/// black 0.0 and white (`Setup.WhiteLevel`) 1.0. `histograms` holds `bins` counts for each of
+/// the `channels` of each frame, frame by frame; reshape to (frames, channels, bins).
+#[pyclass(module = "cinepy", get_all)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameSummary {
+    pub bins: usize,
+    pub channels: usize,
+    pub frames: Vec<usize>,
+    /// Seconds from the trigger, see `CineFile.time_from_trigger`.
+    pub times: Vec<f64>,
+    pub histograms: Vec<u32>,
+    pub mean: Vec<f32>,
+    pub max: Vec<f32>,
+    /// Pixels at or above white.
+    pub saturated: Vec<u32>,
+}
+
+impl FrameSummary {
+    pub fn new(bins: usize, channels: usize) -> Self {
+        Self {
+            bins,
+            channels,
+            frames: Vec::new(),
+            times: Vec::new(),
+            histograms: Vec::new(),
+            mean: Vec::new(),
+            max: Vec::new(),
+            saturated: Vec::new(),
+        }
+    }
+
+    /// Adds a frame, `channel_of` giving the channel of each value. The histogram spans black
+    /// to white in equal bins, values outside land in the first or last.
+    pub fn add(
+        &mut self,
+        frame_no: usize,
+        time: f64,
+        frame: &[f32],
+        channel_of: impl Fn(usize) -> usize,
+    ) {
+        let mut histogram = vec![0u32; self.bins * self.channels];
+        let (mut sum, mut max, mut saturated) = (0.0f64, f32::NEG_INFINITY, 0);
+        for (i, v) in frame.iter().enumerate() {
+            let bin = ((v.clamp(0.0, 1.0) * self.bins as f32) as usize).min(self.bins - 1);
+            histogram[channel_of(i) * self.bins + bin] += 1;
+            sum += *v as f64;
+            max = max.max(*v);
+            saturated += (*v >= 1.0) as u32;
+        }
+        self.frames.push(frame_no);
+        self.times.push(time);
+        self.histograms.extend(histogram);
+        self.mean.push((sum / frame.len().max(1) as f64) as f32);
+        self.max.push(max);
+        self.saturated.push(saturated);
+    }
+
+    /// Appends the frames of `other`, which has the same bins and channels.
+    pub fn append(mut self, other: Self) -> Self {
+        self.frames.extend(other.frames);
+        self.times.extend(other.times);
+        self.histograms.extend(other.histograms);
+        self.mean.extend(other.mean);
+        self.max.extend(other.max);
+        self.saturated.extend(other.saturated);
+        self
+    }
+}
+
+/// Running totals of equally sized frames.
+#[derive(Debug, Clone)]
+pub struct Reducer {
+    count: usize,
+    sums: Vec<f64>,
+    squares: Vec<f64>,
+    min: Vec<f32>,
+    max: Vec<f32>,
+}
+
+impl Reducer {
+    /// Bytes of running totals kept per value.
+    pub const BYTES_PER_VALUE: usize = 2 * size_of::<f64>() + 2 * size_of::<f32>();
+
+    pub fn new(len: usize) -> Self {
+        Self {
+            count: 0,
+            sums: vec![0.0; len],
+            squares: vec![0.0; len],
+            min: vec![f32::INFINITY; len],
+            max: vec![f32::NEG_INFINITY; len],
+        }
+    }
+
+    pub fn add(&mut self, frame: &[f32]) {
+        for (i, v) in frame.iter().enumerate().take(self.sums.len()) {
+            self.sums[i] += *v as f64;
+            self.squares[i] += *v as f64 * *v as f64;
+            self.min[i] = self.min[i].min(*v);
+            self.max[i] = self.max[i].max(*v);
+        }
+        self.count += 1;
+    }
+
+    /// Totals of the frames added to either.
+    pub fn merge(mut self, other: Self) -> Self {
+        for i in 0..self.sums.len() {
+            self.sums[i] += other.sums[i];
+            self.squares[i] += other.squares[i];
+            self.min[i] = self.min[i].min(other.min[i]);
+            self.max[i] = self.max[i].max(other.max[i]);
+        }
+        self.count += other.count;
+        self
+    }
+
+    pub fn mean(&self) -> Vec<f32> {
+        let count = self.count.max(1) as f64;
+        self.sums.iter().map(|s| (s / count) as f32).collect()
+    }
+
+    pub fn std(&self) -> Vec<f32> {
+        let count = self.count.max(1) as f64;
+        self.squares
+            .iter()
+            .zip(&self.sums)
+            .map(|(sq, s)| (sq / count - (s / count).powi(2)).max(0.0).sqrt() as f32)
+            .collect()
+    }
+
+    pub fn finish(self, width: u32, height: u32, channels: usize) -> FrameStats {
+        FrameStats {
+            width,
+            height,
+            channels,
+            count: self.count,
+            mean: self.mean(),
+            std: self.std(),
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reduce() {
+        let frames = [[1.0, 4.0], [3.0, 4.0], [2.0, 1.0], [6.0, 7.0]];
+        let mut all = Reducer::new(2);
+        let (mut first, mut second) = (Reducer::new(2), Reducer::new(2));
+        for (n, frame) in frames.iter().enumerate() {
+            all.add(frame);
+            match n < 2 {
+                true => first.add(frame),
+                false => second.add(frame),
+            }
+        }
+        let stats = all.finish(2, 1, 1);
+        assert_eq!(stats, first.merge(second).finish(2, 1, 1));
+        assert_eq!(stats.count, 4);
+        assert_eq!(stats.mean, vec![3.0, 4.0]);
+        assert_eq!(stats.min, vec![1.0, 1.0]);
+        assert_eq!(stats.max, vec![6.0, 7.0]);
+        assert!((stats.std[1] - 4.5f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_frame_summary() {
+        // Two channels alternating, the last value saturated.
+        let frame = [0.0, 0.3, 0.6, 0.9, -0.1, 1.2];
+        let mut first = FrameSummary::new(4, 2);
+        first.add(3, 0.5, &frame, |i| i % 2);
+        let mut second = FrameSummary::new(4, 2);
+        second.add(4, 0.75, &[0.5; 6], |i| i % 2);
+        let summary = first.append(second);
+        assert_eq!(summary.frames, vec![3, 4]);
+        assert_eq!(summary.times, vec![0.5, 0.75]);
+        assert_eq!(
+            summary.histograms,
+            vec![2, 0, 1, 0, 0, 1, 0, 2, 0, 0, 3, 0, 0, 0, 3, 0]
+        );
+        assert!((summary.mean[0] - 2.9 / 6.0).abs() < 1e-6);
+        assert_eq!(summary.mean[1], 0.5);
+        assert_eq!(summary.max, vec![1.2, 0.5]);
+        assert_eq!(summary.saturated, vec![1, 0]);
+    }
+}
@@ -0,0 +1,43 @@
+// Temporary files for the unit tests, removed again when the test ends whether it passed or not.
+
+use crate::cine;
+use crate::file::CineFile;
+use crate::writer::CineWriter;
+use std::path::PathBuf;
+
+/// A file in the temp directory, deleted when dropped.
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    pub fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(name))
+    }
+
+    pub fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+
+    /// Opens the Cine file written here.
+    pub fn open(&self) -> CineFile {
+        CineFile::new(self.path()).unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Writes `frames` of sensor values, packed to `setup.RealBPP` bits, to a Cine file `name` in the
+/// temp directory.
+pub fn write_cine(name: &str, setup: cine::Setup, frames: &[Vec<u16>]) -> TempFile {
+    let file = TempFile::new(name);
+    let count = frames.len() as u32;
+    let mut writer = CineWriter::new(file.path(), setup, count, setup.RealBPP, 0, None).unwrap();
+    for frame in frames {
+        writer.write_frame(frame.clone(), None, None).unwrap();
+    }
+    writer.finish().unwrap();
+    file
+}
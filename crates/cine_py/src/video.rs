@@ -0,0 +1,253 @@
+// Pure Rust video export; each frame is JPEG encoded and stored in an AVI (RIFF) container as
+// Motion JPEG, which plays in common players without an external encoder.
+
+use crate::cine;
+use crate::writer::write_structs;
+use image::ExtendedColorType;
+use image::ImageResult;
+use image::codecs::jpeg::JpegEncoder;
+use std::io::{self, Seek, SeekFrom, Write};
+
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+// Frame rates are stored as rate / scale.
+const RATE_SCALE: u32 = 1000;
+// Everything before the first frame: RIFF/hdrl headers and the start of the movi list.
+const HEADER_SIZE: u64 = 224;
+// RIFF sizes and idx1 offsets are 32-bit, so plain AVI files stop at 4 GB.
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+pub fn encode_jpeg(rgb: &[u8], width: u32, height: u32, quality: u8) -> ImageResult<Vec<u8>> {
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, quality).encode(
+        rgb,
+        width,
+        height,
+        ExtendedColorType::Rgb8,
+    )?;
+    Ok(jpeg)
+}
+
+pub fn gray_to_rgb(gray: &[u8]) -> Vec<u8> {
+    gray.iter().flat_map(|v| [*v, *v, *v]).collect()
+}
+
+pub struct AviWriter<W: Write + Seek> {
+    writer: W,
+    width: u32,
+    height: u32,
+    fps: f64,
+    // (offset from the movi fourcc, size) of every frame chunk, for the idx1 index.
+    index: Vec<(u32, u32)>,
+    largest_frame: u32,
+    max_size: u64,
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    /// Starts an AVI of `width` x `height` frames. The stream header stores the frame size as
+    /// 16-bit signed values, so sides over 32767 pixels are refused.
+    pub fn new(mut writer: W, width: u32, height: u32, fps: f64) -> io::Result<Self> {
+        if width > i16::MAX as u32 || height > i16::MAX as u32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("AVI frames are at most 32767 pixels a side, not {width}x{height}"),
+            ));
+        }
+        // Reserve the headers, they're rewritten with the final counts by `finish`.
+        writer.write_all(&[0u8; HEADER_SIZE as usize])?;
+        Ok(Self {
+            writer,
+            width,
+            height,
+            fps,
+            index: Vec::new(),
+            largest_frame: 0,
+            max_size: MAX_FILE_SIZE,
+        })
+    }
+
+    /// Appends a frame. Fails without writing anything when the frame and the index would take
+    /// the file past 4 GB.
+    pub fn write_frame(&mut self, jpeg: &[u8]) -> io::Result<()> {
+        let position = self.writer.stream_position()?;
+        let chunk_size = 8 + jpeg.len() as u64 + jpeg.len() as u64 % 2;
+        let index_size = 8 + 16 * (self.index.len() as u64 + 1);
+        if position + chunk_size + index_size > self.max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                format!(
+                    "AVI files can't pass 4 GB, frame {} doesn't fit",
+                    self.index.len()
+                ),
+            ));
+        }
+        let movi_offset = position - (HEADER_SIZE - 4);
+        self.writer.write_all(b"00dc")?;
+        self.writer.write_all(&(jpeg.len() as u32).to_le_bytes())?;
+        self.writer.write_all(jpeg)?;
+        // RIFF chunks are word aligned.
+        if jpeg.len() % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        self.index.push((movi_offset as u32, jpeg.len() as u32));
+        self.largest_frame = self.largest_frame.max(jpeg.len() as u32);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let movi_end = self.writer.stream_position()?;
+        self.writer.write_all(b"idx1")?;
+        self.writer
+            .write_all(&(self.index.len() as u32 * 16).to_le_bytes())?;
+        for (offset, size) in &self.index {
+            self.writer.write_all(b"00dc")?;
+            self.writer.write_all(&AVIIF_KEYFRAME.to_le_bytes())?;
+            self.writer.write_all(&offset.to_le_bytes())?;
+            self.writer.write_all(&size.to_le_bytes())?;
+        }
+        let file_end = self.writer.stream_position()?;
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_headers(file_end, movi_end)?;
+        self.writer.seek(SeekFrom::Start(file_end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_headers(&mut self, file_end: u64, movi_end: u64) -> io::Result<()> {
+        let frames = self.index.len() as u32;
+        let rate = (self.fps * RATE_SCALE as f64).round() as u32;
+        let w = &mut self.writer;
+        let u32le = |v: u32| v.to_le_bytes();
+
+        w.write_all(b"RIFF")?;
+        w.write_all(&u32le(file_end as u32 - 8))?;
+        w.write_all(b"AVI ")?;
+
+        w.write_all(b"LIST")?;
+        w.write_all(&u32le(192))?;
+        w.write_all(b"hdrl")?;
+
+        w.write_all(b"avih")?;
+        w.write_all(&u32le(56))?;
+        w.write_all(&u32le((1_000_000.0 / self.fps).round() as u32))?; // microseconds per frame
+        w.write_all(&u32le((self.largest_frame as f64 * self.fps) as u32))?; // max bytes per second
+        w.write_all(&u32le(0))?; // padding granularity
+        w.write_all(&u32le(AVIF_HASINDEX))?;
+        w.write_all(&u32le(frames))?;
+        w.write_all(&u32le(0))?; // initial frames
+        w.write_all(&u32le(1))?; // streams
+        w.write_all(&u32le(self.largest_frame))?;
+        w.write_all(&u32le(self.width))?;
+        w.write_all(&u32le(self.height))?;
+        w.write_all(&[0u8; 16])?; // reserved
+
+        w.write_all(b"LIST")?;
+        w.write_all(&u32le(116))?;
+        w.write_all(b"strl")?;
+
+        w.write_all(b"strh")?;
+        w.write_all(&u32le(56))?;
+        w.write_all(b"vids")?;
+        w.write_all(b"MJPG")?;
+        w.write_all(&u32le(0))?; // flags
+        w.write_all(&[0u8; 4])?; // priority, language
+        w.write_all(&u32le(0))?; // initial frames
+        w.write_all(&u32le(RATE_SCALE))?;
+        w.write_all(&u32le(rate))?;
+        w.write_all(&u32le(0))?; // start
+        w.write_all(&u32le(frames))?;
+        w.write_all(&u32le(self.largest_frame))?;
+        w.write_all(&u32le(u32::MAX))?; // default quality
+        w.write_all(&u32le(0))?; // sample size, varies per frame
+        w.write_all(&0i16.to_le_bytes())?;
+        w.write_all(&0i16.to_le_bytes())?;
+        w.write_all(&(self.width as i16).to_le_bytes())?;
+        w.write_all(&(self.height as i16).to_le_bytes())?;
+
+        // The stream format is a BITMAPINFOHEADER, the same structure the Cine header uses.
+        let format = cine::BitmapInfoHeader {
+            bi_size: 40,
+            bi_width: self.width as i32,
+            bi_height: self.height as i32,
+            bi_planes: 1,
+            bi_bit_count: 24,
+            bi_compression: u32::from_le_bytes(*b"MJPG"),
+            bi_size_image: self.width * self.height * 3,
+            bi_x_pels_per_meter: 0,
+            bi_y_pels_per_meter: 0,
+            bi_clr_used: 0,
+            bi_clr_important: 0,
+        };
+        w.write_all(b"strf")?;
+        w.write_all(&u32le(40))?;
+        write_structs(&mut *w, &format)?;
+
+        w.write_all(b"LIST")?;
+        w.write_all(&u32le((movi_end - (HEADER_SIZE - 4)) as u32))?;
+        w.write_all(b"movi")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_avi_layout() {
+        let mut avi = AviWriter::new(Cursor::new(Vec::new()), 16, 8, 25.0).unwrap();
+        let frame = encode_jpeg(&gray_to_rgb(&[128u8; 16 * 8]), 16, 8, 80).unwrap();
+        for _ in 0..3 {
+            avi.write_frame(&frame).unwrap();
+        }
+        let bytes = avi.finish().unwrap().into_inner();
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(4) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..12], b"AVI ");
+        assert_eq!(
+            &bytes[HEADER_SIZE as usize - 4..HEADER_SIZE as usize],
+            b"movi"
+        );
+        assert_eq!(
+            &bytes[HEADER_SIZE as usize..HEADER_SIZE as usize + 4],
+            b"00dc"
+        );
+        // avih frame count
+        assert_eq!(u32_at(48), 3);
+        // The first index entry points just past the movi fourcc.
+        let idx1 = bytes.windows(4).position(|w| w == b"idx1").unwrap();
+        assert_eq!(u32_at(idx1 + 16), 4);
+        assert_eq!(u32_at(idx1 + 20) as usize, frame.len());
+    }
+
+    #[test]
+    fn test_frame_size_limit() {
+        let avi = AviWriter::new(Cursor::new(Vec::new()), 32768, 8, 25.0);
+        assert!(avi.is_err_and(|err| err.kind() == io::ErrorKind::InvalidInput));
+        assert!(AviWriter::new(Cursor::new(Vec::new()), 32767, 32767, 25.0).is_ok());
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let mut avi = AviWriter::new(Cursor::new(Vec::new()), 16, 8, 25.0).unwrap();
+        let frame = encode_jpeg(&gray_to_rgb(&[128u8; 16 * 8]), 16, 8, 80).unwrap();
+        let frame_size = 8 + frame.len().next_multiple_of(2) as u64;
+        // Room for two frames and their index entries, not three.
+        avi.max_size = HEADER_SIZE + 2 * frame_size + 8 + 2 * 16;
+        avi.write_frame(&frame).unwrap();
+        avi.write_frame(&frame).unwrap();
+        let err = avi.write_frame(&frame).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::FileTooLarge);
+
+        // The frames that fit still make a complete file.
+        let bytes = avi.finish().unwrap().into_inner();
+        assert_eq!(
+            bytes.len() as u64,
+            HEADER_SIZE + 2 * frame_size + 8 + 2 * 16
+        );
+        assert_eq!(u32::from_le_bytes(bytes[48..52].try_into().unwrap()), 2);
+    }
+}
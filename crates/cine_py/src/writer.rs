@@ -0,0 +1,361 @@
+// Writes Cine files in the layout described in
+// "Phantom SDK Cine File Format Manual Version 3.11.11.806";
+// CINEFILEHEADER, BITMAPINFOHEADER, SETUP, tagged blocks, image offsets then the annotated images.
+
+use crate::cine;
+use crate::decompress::Decompression;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::PathBuf;
+
+// Size of an annotation with no data, AnnotationSize (u32) + ImageSize (u32).
+pub const EMPTY_ANNOTATION_SIZE: u32 = 8;
+
+#[pyclass(module = "cinepy", name = "CineWriter")]
+pub struct CineWriter {
+    file: BufWriter<File>,
+    // Written to `partial_path` and renamed to `path` by `finish`.
+    path: PathBuf,
+    partial_path: PathBuf,
+    cine_file_header: cine::CineFileHeader,
+    bitmap_info_header: cine::BitmapInfoHeader,
+    setup: cine::Setup,
+    compression_type: Decompression,
+    tagged_blocks: Vec<cine::TaggedBlock>,
+    p_images: Vec<i64>,
+    times: Vec<cine::Time64>,
+    exposures: Vec<u32>,
+    // Where the next image goes, only known once the first frame fixes the header layout.
+    next_image_loc: Option<u64>,
+    finished: bool,
+}
+
+#[pymethods]
+impl CineWriter {
+    /// Creates a Cine file for `image_count` frames of `setup.ImWidth` x `setup.ImHeight` pixels.
+    /// It's written to `path` + ".partial" and only moved to `path` by `finish`, a writer dropped
+    /// before then deletes it and leaves `path` as it was.
+    #[new]
+    #[pyo3(signature = (path, setup, image_count, bit_depth=10, first_image_no=0, trigger_time=None))]
+    pub fn new(
+        path: &str,
+        setup: cine::Setup,
+        image_count: u32,
+        bit_depth: u32,
+        first_image_no: i32,
+        trigger_time: Option<cine::Time64>,
+    ) -> PyResult<Self> {
+        let compression_type = Decompression::from_bit_depth(bit_depth)
+            .map_err(|_| PyValueError::new_err("Only 10-bit and 12-bit packing is supported"))?;
+        let (width, height) = (setup.ImWidth, setup.ImHeight);
+        let pixel_count = width as usize * height as usize;
+        let (group_pixels, _) = compression_type.group_size();
+        if pixel_count == 0 || !pixel_count.is_multiple_of(group_pixels) {
+            return Err(PyValueError::new_err(format!(
+                "A {width}x{height} frame can't be packed to {bit_depth} bits"
+            )));
+        }
+        let image_size = u32::try_from(compression_type.packed_size(pixel_count))
+            .map_err(|_| PyValueError::new_err(format!("A {width}x{height} frame is too large")))?;
+
+        let mut setup = setup;
+        setup.Mark = u16::from_le_bytes(*b"ST");
+        setup.Length = mem::size_of::<cine::PackedSetup>() as u16;
+        setup.lFirstImage = first_image_no;
+        setup.dwImageCount = image_count;
+
+        let header_size = mem::size_of::<cine::CineFileHeader>() as u32;
+        let bitmap_size = mem::size_of::<cine::BitmapInfoHeader>() as u32;
+        let cine_file_header = cine::CineFileHeader {
+            type_marker: u16::from_le_bytes(*b"CI"),
+            header_size: header_size as u16,
+            // 0 is gray, 2 is uninterpolated colour straight off the sensor.
+            compression: if setup.CFA == 0 { 0 } else { 2 },
+            version: 1,
+            first_movie_image: first_image_no,
+            total_image_count: image_count,
+            first_image_no,
+            image_count,
+            offset_image_header: header_size,
+            offset_setup: header_size + bitmap_size,
+            // Filled in once the tagged blocks are fixed.
+            offset_image_offsets: 0,
+            trigger_time: trigger_time.unwrap_or(cine::Time64::new(0, 0)),
+        };
+        let bitmap_info_header = cine::BitmapInfoHeader {
+            bi_size: bitmap_size,
+            bi_width: width as i32,
+            bi_height: height as i32,
+            bi_planes: 1,
+            bi_bit_count: 16,
+            bi_compression: compression_type.bi_compression(),
+            bi_size_image: image_size,
+            bi_x_pels_per_meter: 0,
+            bi_y_pels_per_meter: 0,
+            bi_clr_used: 0,
+            bi_clr_important: 0,
+        };
+
+        let partial_path = PathBuf::from(format!("{path}.partial"));
+        Ok(Self {
+            file: BufWriter::new(File::create(&partial_path)?),
+            path: PathBuf::from(path),
+            partial_path,
+            cine_file_header,
+            bitmap_info_header,
+            setup,
+            compression_type,
+            tagged_blocks: Vec::new(),
+            p_images: Vec::with_capacity(image_count as usize),
+            times: Vec::with_capacity(image_count as usize),
+            exposures: Vec::with_capacity(image_count as usize),
+            next_image_loc: None,
+            finished: false,
+        })
+    }
+
+    /// Adds an extra tagged block (analog signals, range data...). Time and exposure blocks are
+    /// always written from the per frame values, so those types are rejected here.
+    pub fn add_tagged_block(&mut self, block_type: u16, data: Vec<u8>) -> PyResult<()> {
+        if self.next_image_loc.is_some() {
+            return Err(PyValueError::new_err(
+                "Tagged blocks must be added before the first frame",
+            ));
+        }
+        if block_type == cine::TAG_TIME_ONLY || block_type == cine::TAG_EXPOSURE_ONLY {
+            return Err(PyValueError::new_err(
+                "Time and exposure blocks are written from the frames",
+            ));
+        }
+        self.tagged_blocks.push(cine::TaggedBlock {
+            block_type,
+            reserved: 0,
+            data,
+        });
+        Ok(())
+    }
+
+    /// Packs and appends a frame of sensor values (0..2^bit_depth, not the 16-bit scaled output of
+    /// `CineFile.get_frame`). Time and exposure default to the trigger time plus the frame period
+    /// and the setup's shutter.
+    #[pyo3(signature = (pixels, time=None, exposure=None))]
+    pub fn write_frame(
+        &mut self,
+        pixels: Vec<u16>,
+        time: Option<cine::Time64>,
+        exposure: Option<u32>,
+    ) -> PyResult<()> {
+        let pixel_count =
+            self.bitmap_info_header.bi_width as usize * self.bitmap_info_header.bi_height as usize;
+        if pixels.len() != pixel_count {
+            return Err(PyValueError::new_err(format!(
+                "Expected {pixel_count} pixels, got {}",
+                pixels.len()
+            )));
+        }
+        let packed = self
+            .compression_type
+            .compress(&pixels)
+            .map_err(|_| PyValueError::new_err("Failed to pack frame"))?;
+        self.write_raw_frame(&packed, Vec::new(), time, exposure)
+    }
+
+    /// Appends an already packed frame as stored in a Cine file, with optional annotation data.
+    #[pyo3(signature = (pixels, annotation=Vec::new(), time=None, exposure=None))]
+    pub fn write_raw_frame(
+        &mut self,
+        pixels: &[u8],
+        annotation: Vec<u8>,
+        time: Option<cine::Time64>,
+        exposure: Option<u32>,
+    ) -> PyResult<()> {
+        if self.finished {
+            return Err(PyValueError::new_err("CineWriter is already finished"));
+        }
+        if self.p_images.len() >= self.cine_file_header.image_count as usize {
+            return Err(PyValueError::new_err(format!(
+                "CineWriter was created for {} frames",
+                self.cine_file_header.image_count
+            )));
+        }
+        if pixels.len() != self.bitmap_info_header.bi_size_image as usize {
+            return Err(PyValueError::new_err(format!(
+                "Expected {} packed bytes, got {}",
+                self.bitmap_info_header.bi_size_image,
+                pixels.len()
+            )));
+        }
+
+        // Frames are written back to back, so only the first one needs to seek past the tables.
+        let image_loc = match self.next_image_loc {
+            Some(loc) => loc,
+            None => {
+                let loc = self.images_start();
+                self.file.seek(SeekFrom::Start(loc))?;
+                loc
+            }
+        };
+        let frame_index = self.p_images.len();
+        let annotation_size = EMPTY_ANNOTATION_SIZE + annotation.len() as u32;
+        self.file.write_all(&annotation_size.to_le_bytes())?;
+        self.file.write_all(&annotation)?;
+        self.file.write_all(&(pixels.len() as u32).to_le_bytes())?;
+        self.file.write_all(pixels)?;
+
+        self.p_images.push(image_loc as i64);
+        self.times
+            .push(time.unwrap_or_else(|| self.default_time(frame_index)));
+        self.exposures
+            .push(exposure.unwrap_or_else(|| self.default_exposure()));
+        self.next_image_loc = Some(image_loc + annotation_size as u64 + pixels.len() as u64);
+        Ok(())
+    }
+
+    /// Writes the headers, tagged blocks and offsets. Every declared frame must have been written.
+    pub fn finish(&mut self) -> PyResult<()> {
+        if self.finished {
+            return Ok(());
+        }
+        if self.p_images.len() != self.cine_file_header.image_count as usize {
+            return Err(PyValueError::new_err(format!(
+                "{} of {} frames were written",
+                self.p_images.len(),
+                self.cine_file_header.image_count
+            )));
+        }
+        self.cine_file_header.offset_image_offsets = self.offsets_start() as u32;
+        let packed_setup = cine::PackedSetup::from(&self.setup);
+
+        self.file.seek(SeekFrom::Start(0))?;
+        write_structs(&mut self.file, &self.cine_file_header)?;
+        write_structs(&mut self.file, &self.bitmap_info_header)?;
+        write_structs(&mut self.file, &packed_setup)?;
+        for block in self.all_tagged_blocks() {
+            self.file.write_all(&block.to_bytes())?;
+        }
+        for p_image in &self.p_images {
+            self.file.write_all(&p_image.to_le_bytes())?;
+        }
+        self.file.flush()?;
+        std::fs::rename(&self.partial_path, &self.path)?;
+        self.finished = true;
+        Ok(())
+    }
+
+    pub fn frames_written(&self) -> usize {
+        self.p_images.len()
+    }
+}
+
+impl CineWriter {
+    fn all_tagged_blocks(&self) -> Vec<cine::TaggedBlock> {
+        let mut blocks = vec![
+            cine::TaggedBlock::time_only(&self.times),
+            cine::TaggedBlock::exposure_only(&self.exposures),
+        ];
+        blocks.extend(self.tagged_blocks.iter().cloned());
+        blocks
+    }
+
+    // The tagged blocks sit between the setup and the offsets, their sizes only depend on the
+    // image count so the layout is fixed before any frame is written.
+    fn offsets_start(&self) -> u64 {
+        let image_count = self.cine_file_header.image_count as usize;
+        let time_and_exposure = 2 * cine::TAGGED_BLOCK_HEADER_SIZE
+            + image_count * (mem::size_of::<cine::Time64>() + mem::size_of::<u32>());
+        let extra: usize = self.tagged_blocks.iter().map(|b| b.block_size()).sum();
+        self.cine_file_header.offset_setup as u64
+            + mem::size_of::<cine::PackedSetup>() as u64
+            + (time_and_exposure + extra) as u64
+    }
+
+    fn images_start(&self) -> u64 {
+        self.offsets_start()
+            + self.cine_file_header.image_count as u64 * mem::size_of::<i64>() as u64
+    }
+
+    fn default_time(&self, frame_index: usize) -> cine::Time64 {
+        let image_no = self.cine_file_header.first_image_no as f64 + frame_index as f64;
+        let trigger = self.cine_file_header.trigger_time.as_secs_f64();
+        cine::Time64::from_secs_f64(trigger + image_no / self.setup.frame_rate())
+    }
+
+    fn default_exposure(&self) -> u32 {
+        (self.setup.ShutterNs as f64 * 1e-9 * 4_294_967_296.0) as u32
+    }
+}
+
+impl Drop for CineWriter {
+    fn drop(&mut self) {
+        // Without the headers and offsets the frames can't be read back.
+        if !self.finished {
+            let _ = std::fs::remove_file(&self.partial_path);
+        }
+    }
+}
+
+pub(crate) fn write_structs<T: Copy, W: Write>(mut writer: W, value: &T) -> io::Result<()> {
+    let buffer_ptr = value as *const T as *const u8;
+    let buffer = unsafe { std::slice::from_raw_parts(buffer_ptr, mem::size_of::<T>()) };
+    writer.write_all(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFile;
+
+    #[test]
+    fn test_write_and_read_back() {
+        let file = TempFile::new("cine_py_writer_test.cine");
+        let setup = cine::Setup::new(8, 4, 10, 2000);
+        let frames: Vec<Vec<u16>> = (0..3u16)
+            .map(|f| (0..32u16).map(|p| (p * 31 + f) & 0x3FF).collect())
+            .collect();
+
+        let mut writer = CineWriter::new(file.path(), setup, 3, 10, 5, None).unwrap();
+        for frame in &frames {
+            writer.write_frame(frame.clone(), None, None).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut cine_file = file.open();
+        assert_eq!(cine_file.cine_file_header.image_count, 3);
+        assert_eq!(cine_file.cine_file_header.first_image_no, 5);
+        assert_eq!(cine_file.bitmap_info_header.bi_width, 8);
+        assert_eq!(cine_file.setup_revision, cine::SetupRevision::Current);
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(&cine_file.get_sensor_frame(i).unwrap(), frame);
+        }
+    }
+
+    #[test]
+    fn test_unfinished_writer() {
+        let file = TempFile::new("cine_py_writer_unfinished.cine");
+        std::fs::write(file.path(), b"old").unwrap();
+        let setup = cine::Setup::new(8, 4, 10, 2000);
+        let mut writer = CineWriter::new(file.path(), setup, 2, 10, 0, None).unwrap();
+        writer.write_frame(vec![0; 32], None, None).unwrap();
+        assert!(writer.finish().is_err());
+        drop(writer);
+
+        // The half written file is gone and the old one untouched.
+        assert!(!std::path::Path::new(&format!("{}.partial", file.path())).exists());
+        assert_eq!(std::fs::read(file.path()).unwrap(), b"old");
+    }
+
+    #[test]
+    fn test_frame_size_checks() {
+        let file = TempFile::new("cine_py_writer_sizes.cine");
+        // Odd pixel counts don't fill the packing groups, the largest frames overflow i32 pixel
+        // counts and u32 image sizes.
+        for (width, height) in [(5, 3), (u16::MAX, u16::MAX), (65532, 65532)] {
+            let setup = cine::Setup::new(width, height, 10, 2000);
+            assert!(CineWriter::new(file.path(), setup, 1, 10, 0, None).is_err());
+        }
+        assert!(!std::path::Path::new(&format!("{}.partial", file.path())).exists());
+    }
+}